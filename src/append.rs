@@ -0,0 +1,100 @@
+// Stores data after IEND, where decoders never look but plain copies keep every byte
+use crate::error::{Error, Result};
+use crate::png::Png;
+
+/// Ends the trailing data when it holds a message, so other appended data is left alone
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'A'];
+/// The data's length as a u32, followed by the magic
+const FOOTER_LENGTH: usize = 4 + MAGIC.len();
+
+/// Appends `data` after IEND, replacing a message appended before but keeping any other
+/// trailing data in front of it
+pub fn embed(png: &mut Png, data: &[u8]) -> Result<()> {
+    let length = u32::try_from(data.len()).map_err(|_| Error::CapacityExceeded {
+        needed: data.len(),
+        available: u32::MAX as usize,
+    })?;
+
+    let mut trailing_data = png.trailing_data().to_vec();
+    if let Some(start) = message_start(&trailing_data) {
+        trailing_data.truncate(start);
+    }
+    trailing_data.extend_from_slice(data);
+    trailing_data.extend_from_slice(&length.to_be_bytes());
+    trailing_data.extend_from_slice(&MAGIC);
+    png.set_trailing_data(trailing_data);
+    Ok(())
+}
+
+/// Reads back data appended by `embed`
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let trailing_data = png.trailing_data();
+    match message_start(trailing_data) {
+        Some(start) => Ok(trailing_data[start..trailing_data.len() - FOOTER_LENGTH].to_vec()),
+        None => Err(Error::InvalidPayload(String::from(
+            "no message is appended after IEND",
+        ))),
+    }
+}
+
+/// Drops a message appended by `embed`, returning whether there was one
+pub fn remove(png: &mut Png) -> bool {
+    match message_start(png.trailing_data()) {
+        Some(start) => {
+            let mut trailing_data = png.trailing_data().to_vec();
+            trailing_data.truncate(start);
+            png.set_trailing_data(trailing_data);
+            true
+        }
+        None => false,
+    }
+}
+
+// @notice: Where the appended message starts, if the trailing data ends with one
+fn message_start(trailing_data: &[u8]) -> Option<usize> {
+    let body = trailing_data.strip_suffix(&MAGIC)?;
+    let (body, length) = body.split_at(body.len().checked_sub(4)?);
+    let length = u32::from_be_bytes(length.try_into().ok()?) as usize;
+    body.len().checked_sub(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::new(b"IHDR"), vec![0; 13]),
+            Chunk::new(ChunkType::new(b"IEND"), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let mut png = testing_png();
+        assert!(extract(&png).is_err());
+
+        embed(&mut png, b"After the end").unwrap();
+        let bytes = png.as_bytes();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(extract(&png).unwrap(), b"After the end");
+    }
+
+    #[test]
+    fn test_keeps_other_trailing_data() {
+        let mut png = testing_png();
+        png.set_trailing_data(b"PK zip archive".to_vec());
+
+        embed(&mut png, b"first").unwrap();
+        embed(&mut png, b"second").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"second");
+        assert!(png.trailing_data().starts_with(b"PK zip archive"));
+
+        assert!(remove(&mut png));
+        assert_eq!(png.trailing_data(), b"PK zip archive");
+        assert!(!remove(&mut png));
+    }
+}
@@ -1,4 +1,10 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgEnum, Parser, Subcommand};
+
+use crate::chunk_type::ChunkType;
+use crate::fragment::DEFAULT_MAXIMUM_SIZE;
+
+/// Chunk type used when none is given on the command line
+pub const DEFAULT_CHUNK_TYPE: &str = "ruSt";
 
 /// Pngme CLI
 #[derive(Debug, Parser)]
@@ -8,6 +14,28 @@ use clap::{Parser, Subcommand};
 pub struct Cli {
     #[clap(subcommand)]
     pub command: CliCommand,
+    /// How results and errors are printed
+    #[clap(long, global = true, arg_enum, default_value = "text")]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum OutputFormat {
+    /// Coloured text for people
+    Text,
+    /// A single JSON document for scripts
+    Json,
+}
+
+/// Where in the image a message is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ArgEnum)]
+pub enum Method {
+    /// In ancillary chunks of --chunk-type
+    Chunk,
+    /// In the low bits of the pixels, surviving tools that strip chunks
+    Lsb,
+    /// After IEND, where image tools don't look
+    Append,
 }
 
 #[derive(Debug, Subcommand)]
@@ -17,26 +45,197 @@ pub enum CliCommand {
     Encode {
         #[clap(required = true)]
         file_path: String,
-        #[clap(required = true)]
-        message: String,
+        /// Message to store, `-` reads it from stdin
+        #[clap(required_unless_present = "file", conflicts_with = "file")]
+        message: Option<String>,
         #[clap(required = false)]
         output_file: Option<String>,
+        /// Write the encoded png here instead of overwriting file_path, works with --file
+        #[clap(short, long, conflicts_with = "output-file")]
+        output: Option<String>,
+        /// Store the contents of this file instead of a message, `-` reads stdin
+        #[clap(long)]
+        file: Option<String>,
+        /// Chunk type the message is stored in
+        #[clap(long, default_value = DEFAULT_CHUNK_TYPE)]
+        chunk_type: ChunkType,
+        /// Encrypt the message with a key derived from --passphrase
+        #[clap(long, requires = "passphrase", conflicts_with = "recipient")]
+        encrypt: bool,
+        /// Passphrase used by --encrypt
+        #[clap(long, env = "PNGME_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// Encrypt the message to the public key in this file, can be repeated
+        #[clap(long, multiple_occurrences = true)]
+        recipient: Vec<String>,
+        /// Deflate the message before storing it
+        #[clap(long)]
+        compress: bool,
+        /// Split the message across chunks holding at most this many bytes
+        #[clap(long, default_value_t = DEFAULT_MAXIMUM_SIZE)]
+        max_chunk_size: usize,
+        /// Name the message, replacing any message with the same name
+        #[clap(long)]
+        name: Option<String>,
+        /// Where to hide the message
+        #[clap(long, arg_enum, default_value = "chunk")]
+        method: Method,
     },
     /// Get message of chunk_type from png at file_path
     #[clap(arg_required_else_help = true)]
     Decode {
         #[clap(required = true)]
         file_path: String,
+        /// Chunk type the message is stored in
+        #[clap(long, default_value = DEFAULT_CHUNK_TYPE)]
+        chunk_type: ChunkType,
+        /// Passphrase for messages stored with --encrypt
+        #[clap(long, env = "PNGME_PASSPHRASE", hide_env_values = true)]
+        passphrase: Option<String>,
+        /// Secret key file for messages stored with --recipient
+        #[clap(long)]
+        identity: Option<String>,
+        /// Write the raw payload to this file instead of printing it, `-` for stdout
+        #[clap(long)]
+        out: Option<String>,
+        /// Decode the message with this name instead of the first one
+        #[clap(long)]
+        name: Option<String>,
+        /// Where the message is hidden
+        #[clap(long, arg_enum, default_value = "chunk")]
+        method: Method,
     },
     /// Remove message of chunk_type from png at file_path
     #[clap(arg_required_else_help = true)]
     Remove {
         #[clap(required = true)]
         file_path: String,
+        /// Chunk type the message is stored in
+        #[clap(long, default_value = DEFAULT_CHUNK_TYPE)]
+        chunk_type: ChunkType,
+        /// Remove the message with this name instead of the first one
+        #[clap(long, conflicts_with = "all")]
+        name: Option<String>,
+        /// Remove every message stored in chunk_type
+        #[clap(long)]
+        all: bool,
+        /// Where the message is hidden, messages hidden with lsb can only be encoded over
+        #[clap(long, arg_enum, default_value = "chunk")]
+        method: Method,
+    },
+    /// List every message hidden in the png at file_path
+    #[clap(arg_required_else_help = true)]
+    List {
+        #[clap(required = true)]
+        file_path: String,
+        /// Only list messages stored in this chunk type
+        #[clap(long)]
+        chunk_type: Option<ChunkType>,
     },
     #[clap(arg_required_else_help = true)]
     Print {
         #[clap(required = true)]
         file_path: String,
     },
+    /// Generate a key pair for --recipient and --identity
+    #[clap(arg_required_else_help = true)]
+    Keygen {
+        /// File the secret key is written to, the public key goes to the same path plus
+        /// `.pub`. Neither may exist yet.
+        #[clap(required = true)]
+        identity_file: String,
+        /// Generate an Ed25519 key for sign and verify instead
+        #[clap(long)]
+        signing: bool,
+    },
+    /// Sign the critical chunks and message of the png at file_path
+    #[clap(arg_required_else_help = true)]
+    Sign {
+        #[clap(required = true)]
+        file_path: String,
+        /// Signing key file made by `keygen --signing`
+        #[clap(long, required = true)]
+        key: String,
+        /// Message chunk type covered along with the critical chunks
+        #[clap(long, default_value = DEFAULT_CHUNK_TYPE)]
+        chunk_type: ChunkType,
+        /// Cover exactly these chunk types instead, can be repeated
+        #[clap(long, multiple_occurrences = true)]
+        cover: Vec<ChunkType>,
+        /// Write the signature to this file instead of embedding it
+        #[clap(long)]
+        detached: Option<String>,
+    },
+    /// Check the signature of the png at file_path
+    #[clap(arg_required_else_help = true)]
+    Verify {
+        #[clap(required = true)]
+        file_path: String,
+        /// The verifying key in this file must have made the signature
+        #[clap(long, required = true)]
+        key: String,
+        /// Read a detached signature from this file instead of the embedded one
+        #[clap(long)]
+        signature: Option<String>,
+    },
+    /// List every chunk of the png at file_path with its offset, flags and contents
+    #[clap(arg_required_else_help = true)]
+    Info {
+        #[clap(required = true)]
+        file_path: String,
+    },
+    /// Fix wrong CRCs, cut-off chunks and a missing IEND in the png at file_path
+    #[clap(arg_required_else_help = true)]
+    Repair {
+        #[clap(required = true)]
+        file_path: String,
+        /// Write the repaired png here instead of overwriting file_path
+        #[clap(required = false)]
+        output_file: Option<String>,
+    },
+    /// Check the png at file_path follows the spec's chunk ordering rules
+    #[clap(arg_required_else_help = true)]
+    Check {
+        #[clap(required = true)]
+        file_path: String,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_outputs(args: &[&str]) -> (Option<String>, Option<String>) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        match cli.command {
+            CliCommand::Encode {
+                output_file,
+                output,
+                ..
+            } => (output_file, output),
+            other => panic!("expected encode, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_encode_output() {
+        let (output_file, output) = encode_outputs(&["pngme", "encode", "in.png", "hi", "out.png"]);
+        assert_eq!(output_file.as_deref(), Some("out.png"));
+        assert_eq!(output, None);
+
+        let (output_file, output) = encode_outputs(&[
+            "pngme", "encode", "in.png", "--file", "msg.txt", "--output", "out.png",
+        ]);
+        assert_eq!(output_file, None);
+        assert_eq!(output.as_deref(), Some("out.png"));
+    }
+
+    #[test]
+    fn test_encode_output_conflicts() {
+        assert!(
+            Cli::try_parse_from(["pngme", "encode", "in.png", "hi", "a.png", "-o", "b.png"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from(["pngme", "encode", "in.png", "--file", "f", "hi"]).is_err());
+    }
 }
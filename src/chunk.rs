@@ -1,11 +1,13 @@
-use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
-use std::io::{BufReader, Read};
+use std::io::Write;
 
 use crate::chunk_type::*;
+use crate::error::{Error, Result};
+use crate::payload::Payload;
+use crate::zlib::InflateLimits;
 
-const MAXIMUM_LENGTH: u32 = (1 << 31) - 1;
+pub(crate) const MAXIMUM_LENGTH: u32 = (1 << 31) - 1;
 
 #[derive(Debug)]
 pub struct Chunk {
@@ -13,19 +15,75 @@ pub struct Chunk {
     chunk_type: ChunkType,
     chunk_data: Vec<u8>,
     crc: u32,
+    /// Where the chunk started in the input it was parsed from
+    offset: Option<usize>,
 }
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, chunk_data: Vec<u8>) -> Chunk {
-        let crc = crc::crc32::checksum_ieee(&[&chunk_type.bytes(), chunk_data.as_slice()].concat());
+        let crc = checksum(&chunk_type, &chunk_data);
         Chunk {
             length: chunk_data.len() as u32,
             chunk_type,
             chunk_data,
             crc,
+            offset: None,
         }
     }
 
+    /// Builds a chunk read from a file, checking the stored CRC against its contents
+    pub(crate) fn from_parts(
+        chunk_type: ChunkType,
+        chunk_data: Vec<u8>,
+        crc: u32,
+    ) -> Result<Chunk> {
+        let chunk = Chunk::new(chunk_type, chunk_data);
+
+        if chunk.crc != crc {
+            return Err(Error::CrcMismatch {
+                chunk_type: chunk.chunk_type,
+                expected: crc,
+                actual: chunk.crc,
+                index: None,
+                offset: None,
+            });
+        }
+
+        Ok(chunk)
+    }
+
+    /// Builds a chunk read from a file exactly as stored, even if the CRC is wrong
+    pub(crate) fn from_parts_unchecked(
+        chunk_type: ChunkType,
+        chunk_data: Vec<u8>,
+        crc: u32,
+    ) -> Chunk {
+        Chunk {
+            crc,
+            ..Chunk::new(chunk_type, chunk_data)
+        }
+    }
+
+    /// Records where the chunk started in the input it was parsed from
+    pub(crate) fn located_at(self, offset: usize) -> Chunk {
+        Chunk {
+            offset: Some(offset),
+            ..self
+        }
+    }
+
+    /// Byte offset of the chunk's length field in the input it was parsed from, `None` for
+    /// chunks built in memory. It isn't updated when chunks move, `info::chunk_infos` gives
+    /// the offsets of the file as it would be written.
+    pub fn offset(&self) -> Option<usize> {
+        self.offset
+    }
+
+    /// Whether the stored CRC matches the chunk's type and data
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == checksum(&self.chunk_type, &self.chunk_data)
+    }
+
     pub fn length(&self) -> u32 {
         self.length
     }
@@ -42,8 +100,15 @@ impl Chunk {
         self.crc
     }
 
-    pub fn data_as_string(&self) -> Result<String, Box<dyn std::error::Error>> {
-        Ok(String::from_utf8(self.chunk_data.clone()).unwrap())
+    pub fn data_as_string(&self) -> Result<String> {
+        Ok(String::from_utf8(self.chunk_data.clone())?)
+    }
+
+    /// Like `data_as_string`, but inflates the message first if it was stored compressed,
+    /// failing once it inflates past `limits`, such as `Png::inflate_limits`
+    pub fn payload_as_string(&self, limits: InflateLimits) -> Result<String> {
+        let payload = Payload::from_bytes_limited(self.data(), limits)?;
+        Ok(String::from_utf8(payload.data)?)
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
@@ -56,6 +121,15 @@ impl Chunk {
             .copied()
             .collect()
     }
+
+    /// Serializes the chunk straight into `writer` without building an intermediate buffer
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.length().to_be_bytes())?;
+        writer.write_all(&self.chunk_type().bytes())?;
+        writer.write_all(self.data())?;
+        writer.write_all(&self.crc().to_be_bytes())?;
+        Ok(())
+    }
 }
 
 impl Display for Chunk {
@@ -65,73 +139,58 @@ impl Display for Chunk {
             "Length: {}\nChunk_Type: {}\nChunk_Data: {}\nCRC: {}",
             self.length(),
             self.chunk_type(),
-            self.data_as_string().unwrap(),
+            String::from_utf8_lossy(self.data()),
             self.crc()
-        )
-    }
-}
-
-#[derive(Debug)]
-pub struct ChunkDecodingError {
-    reason: String,
-}
-
-impl ChunkDecodingError {
-    fn boxed(reason: String) -> Box<Self> {
-        Box::new(Self { reason })
-    }
-}
-
-impl Display for ChunkDecodingError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bad chunk: {}", self.reason)
+        )?;
+        match self.offset {
+            Some(offset) => write!(f, "\nOffset: {}", offset),
+            None => Ok(()),
+        }
     }
 }
 
-impl Error for ChunkDecodingError {}
-
 impl TryFrom<&[u8]> for Chunk {
-    type Error = Box<dyn std::error::Error>;
+    type Error = Error;
 
-    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(bytes);
-        let mut buff: [u8; 4] = [0; 4];
-
-        reader.read_exact(&mut buff).unwrap();
-        let length = u32::from_be_bytes(buff);
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        let length = u32::from_be_bytes(read_array(bytes, 0)?);
 
         if length > MAXIMUM_LENGTH {
-            return Err(ChunkDecodingError::boxed(String::from(
-                "Length is too long.",
-            )));
+            return Err(Error::LengthOverflow(u64::from(length)));
         }
 
-        reader.read_exact(&mut buff)?;
-        let chunk_type = ChunkType::try_from(buff).unwrap();
-
-        let mut chunk_data: Vec<u8> = vec![0; usize::try_from(length)?];
-        reader.read_exact(&mut chunk_data).unwrap();
+        let chunk_type = ChunkType::try_from(read_array(bytes, 4)?)?;
+
+        let data_end = usize::try_from(length)
+            .ok()
+            .and_then(|length| length.checked_add(8))
+            .ok_or(Error::LengthOverflow(u64::from(length)))?;
+        let chunk_data = bytes
+            .get(8..data_end)
+            .ok_or(Error::Truncated {
+                offset: bytes.len(),
+            })?
+            .to_vec();
 
-        reader.read_exact(&mut buff).unwrap();
-        let crc = u32::from_be_bytes(buff);
+        let crc = u32::from_be_bytes(read_array(bytes, data_end)?);
 
-        let actual_crc =
-            crc::crc32::checksum_ieee(&[&chunk_type.bytes(), chunk_data.as_slice()].concat());
+        Chunk::from_parts(chunk_type, chunk_data, crc)
+    }
+}
 
-        if crc != actual_crc {
-            return Err(ChunkDecodingError::boxed(format!(
-                "Invalid crc expected {}, got {}",
-                crc, actual_crc
-            )));
-        }
+// @notice: CRC of a chunk, covering its type and data but not its length
+pub(crate) fn checksum(chunk_type: &ChunkType, chunk_data: &[u8]) -> u32 {
+    crc::crc32::checksum_ieee(&[&chunk_type.bytes(), chunk_data].concat())
+}
 
-        Ok(Chunk {
-            length,
-            chunk_type,
-            chunk_data,
-            crc,
+// @notice: Reads the 4 bytes starting at `at`, failing if the input ends first
+fn read_array(bytes: &[u8], at: usize) -> Result<[u8; 4]> {
+    bytes
+        .get(at..at + 4)
+        .and_then(|slice| slice.try_into().ok())
+        .ok_or(Error::Truncated {
+            offset: bytes.len(),
         })
-    }
 }
 
 #[cfg(test)]
@@ -189,6 +248,33 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_chunk_payload_string() {
+        let chunk = testing_chunk();
+        assert_eq!(
+            chunk.payload_as_string(InflateLimits::default()).unwrap(),
+            chunk.data_as_string().unwrap()
+        );
+
+        let mut payload = Payload::new(b"Squeeze me".to_vec());
+        payload.compressed = true;
+        let chunk = Chunk::new(ChunkType::new(b"RuSt"), payload.to_bytes().unwrap());
+        assert!(chunk.data_as_string().is_err());
+        assert_eq!(
+            chunk.payload_as_string(InflateLimits::default()).unwrap(),
+            "Squeeze me"
+        );
+
+        let limits = InflateLimits {
+            max_ratio: 1,
+            max_size: 4,
+        };
+        assert!(matches!(
+            chunk.payload_as_string(limits),
+            Err(Error::LimitExceeded { .. })
+        ));
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -243,6 +329,39 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_chunk_write_to() {
+        let chunk = testing_chunk();
+        let mut bytes = Vec::new();
+        chunk.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, chunk.as_bytes());
+    }
+
+    #[test]
+    fn test_truncated_chunk_from_bytes() {
+        let bytes = testing_chunk().as_bytes();
+
+        for end in 0..bytes.len() {
+            let chunk = Chunk::try_from(&bytes[..end]);
+            assert!(matches!(chunk, Err(Error::Truncated { .. })));
+        }
+    }
+
+    #[test]
+    fn test_chunk_length_overflow() {
+        let bytes = [255, 255, 255, 255, 82, 117, 83, 116];
+        let chunk = Chunk::try_from(bytes.as_ref());
+        assert!(matches!(chunk, Err(Error::LengthOverflow(_))));
+    }
+
+    #[test]
+    fn test_invalid_utf8_chunk_string() {
+        let chunk_type = ChunkType::from_str("RuSt").unwrap();
+        let chunk = Chunk::new(chunk_type, vec![0xff, 0xfe]);
+        assert!(matches!(chunk.data_as_string(), Err(Error::Utf8(_))));
+        let _chunk_string = format!("{}", chunk);
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -1,27 +1,15 @@
 // Implementation of Chunk Type section of PNG spec[http://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html]
 use std::convert::TryInto;
-use std::error::Error;
-use std::fmt::{self, Display};
+use std::fmt;
 use std::str::FromStr;
 
+use crate::error::{Error, Result};
+
 #[derive(PartialEq, Debug, Clone)]
 pub struct ChunkType {
     bytes: [u8; 4],
 }
 
-#[derive(Debug)]
-pub enum ChunkTypeErr {
-    InvalidArgs,
-}
-
-impl Display for ChunkTypeErr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid arguments")
-    }
-}
-
-impl Error for ChunkTypeErr {}
-
 impl ChunkType {
     // @notice ChunkType constructor
     // @param _bytes Bytes of ChunkType
@@ -58,48 +46,38 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool {
         self.bytes[3].is_ascii_lowercase()
     }
-
 }
 
 // @notice: Constructs a ChunkType instance from a 4 byte Array
 impl TryFrom<[u8; 4]> for ChunkType {
-    type Error = ChunkTypeErr;
-
-    fn try_from(bytes: [u8; 4]) -> Result<Self, ChunkTypeErr> {
-        Ok(ChunkType { bytes })
+    type Error = Error;
+
+    fn try_from(bytes: [u8; 4]) -> Result<Self> {
+        match bytes.iter().all(|c| c.is_ascii_alphabetic()) {
+            true => Ok(ChunkType { bytes }),
+            _ => Err(Error::InvalidChunkType(
+                String::from_utf8_lossy(&bytes).into_owned(),
+            )),
+        }
     }
 }
 
 // @notice: Constructs a ChunkType instance from a &str
 impl FromStr for ChunkType {
-    type Err = ChunkTypeErr;
-
-    fn from_str(s: &str) -> Result<Self, ChunkTypeErr> {
-
-        if s.chars().count() != 4 {
-            Err(ChunkTypeErr::InvalidArgs)
-
-        } else {
-
-            let temp = ChunkType {
-                bytes: s.as_bytes().try_into().unwrap(),
-            };
+    type Err = Error;
 
-            match temp.bytes.iter().all(|c| c.is_ascii_alphabetic()) {
-                true => Ok(temp),
-                _ => Err(ChunkTypeErr::InvalidArgs),
-            }
-        }
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes: [u8; 4] = s
+            .as_bytes()
+            .try_into()
+            .map_err(|_| Error::InvalidChunkType(String::from(s)))?;
+        ChunkType::try_from(bytes)
     }
 }
 
 impl fmt::Display for ChunkType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{} {} {} {}",
-            self.bytes[0], self.bytes[1], self.bytes[2], self.bytes[3]
-        )
+        write!(f, "{}", String::from_utf8_lossy(&self.bytes))
     }
 }
 
@@ -187,6 +165,12 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    pub fn test_chunk_type_from_invalid_bytes() {
+        assert!(ChunkType::try_from([32, 117, 83, 116]).is_err());
+        assert!(ChunkType::from_str("RuStt").is_err());
+    }
+
     #[test]
     pub fn test_chunk_type_string() {
         let chunk = ChunkType::from_str("RuSt").unwrap();
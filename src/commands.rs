@@ -1,87 +1,647 @@
-use std::borrow::Borrow;
-use std::fs::File;
-use std::io::{Error, Read, Write};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
 use std::path::Path;
+use std::str::FromStr;
 
-use colored::Colorize;
-
-use crate::args::CliCommand;
+use crate::append;
+use crate::args::{CliCommand, Method};
 use crate::chunk::Chunk;
 use crate::chunk_type::ChunkType;
-use crate::png::Png;
+use crate::crypto::{
+    decrypt, encrypt_to_recipients, encrypt_with_passphrase, is_encrypted, Identity, Recipient,
+};
+use crate::error::{Error, Result};
+use crate::fragment::split;
+use crate::info::chunk_infos;
+use crate::lsb;
+use crate::message::{label, messages, unlabel, Message, MessageInfo};
+use crate::output::Output;
+use crate::payload::Payload;
+use crate::png::{InsertPosition, ParseOptions, Png};
+use crate::reader::ChunkReader;
+use crate::repair::repair;
+use crate::signature::{self, default_covered_types, Signer, Verifier};
+
+fn get_png(file_path: &String) -> Result<Png> {
+    let path = Path::new(file_path);
+    let file = File::open(path)?;
 
-const DEFAULT_CHUNK_TYPE: &str = "ruSt";
+    Png::from_reader(BufReader::new(file))
+}
 
-fn get_png(file_path: &String) -> Result<Png, Box<dyn std::error::Error>> {
+fn get_chunk_reader(file_path: &String) -> Result<ChunkReader<BufReader<File>>> {
     let path = Path::new(file_path);
-    let mut file = File::open(path)?;
+    let file = File::open(path)?;
+
+    ChunkReader::new(BufReader::new(file))
+}
+
+// @notice: Reads only the chunks of chunk_type, checking but never holding the image data
+// and every other chunk, so decoding doesn't buffer the whole file. Older versions wrote
+// messages after IEND, so chunks of chunk_type there are read too, within the default limits.
+fn read_message_chunks(file_path: &String, chunk_type: &ChunkType) -> Result<Png> {
+    let options = ParseOptions::default();
+    let mut reader = get_chunk_reader(file_path)?;
+    let mut chunks = Vec::new();
+    while let Some(chunk) = reader.find_chunk(&chunk_type.to_string())? {
+        chunks.push(chunk);
+    }
+    if reader.ended() {
+        let remaining = options
+            .max_total_size
+            .saturating_sub(reader.offset() as u64);
+        let rest = reader.read_remaining(remaining)?;
+        if rest.len() as u64 > remaining {
+            return Err(Error::LimitExceeded {
+                limit: "total size",
+                maximum: options.max_total_size,
+            });
+        }
+        chunks.extend(
+            legacy_chunks(&rest, reader.offset() - rest.len(), &options)?
+                .into_iter()
+                .filter(|chunk| chunk.chunk_type() == chunk_type),
+        );
+    }
+    Ok(Png::from_chunks(chunks))
+}
+
+// @notice: The intact chunks at the start of the data after IEND, up to the first thing
+// that isn't one. `offset` is where `bytes` starts in the file.
+fn legacy_chunks(
+    mut bytes: &[u8],
+    mut offset: usize,
+    options: &ParseOptions,
+) -> Result<Vec<Chunk>> {
+    let mut chunks = Vec::new();
+    while let Ok(chunk) = Chunk::try_from(bytes) {
+        if chunk.length() > options.max_chunk_size {
+            return Err(Error::LimitExceeded {
+                limit: "chunk size",
+                maximum: u64::from(options.max_chunk_size),
+            });
+        }
+        if chunks.len() >= options.max_chunks {
+            return Err(Error::LimitExceeded {
+                limit: "chunk count",
+                maximum: options.max_chunks as u64,
+            });
+        }
+        let length = 12 + chunk.data().len();
+        chunks.push(chunk.located_at(offset));
+        bytes = &bytes[length..];
+        offset += length;
+    }
+    Ok(chunks)
+}
+
+// @notice: Finds the first message named `name`, or the first message at all
+fn find_message(png: &Png, chunk_type: &ChunkType, name: Option<&str>) -> Result<Message> {
+    messages(png, Some(chunk_type))
+        .into_iter()
+        .find(|message| message.matches(name))
+        .ok_or_else(|| match name {
+            Some(name) => Error::MessageNotFound(String::from(name)),
+            None => Error::ChunkNotFound(chunk_type.to_string()),
+        })
+}
+
+// @notice: Strips the label off a message hidden outside of chunks, failing unless it's
+// named `name` when one is asked for
+fn unlabel_named(data: Vec<u8>, name: Option<&str>) -> Result<Vec<u8>> {
+    let (found, data) = unlabel(data)?;
+    match name {
+        Some(name) if found.as_deref() != Some(name) => {
+            Err(Error::MessageNotFound(String::from(name)))
+        }
+        _ => Ok(data),
+    }
+}
+
+// @notice: Removes the chunks at `indices` from png
+fn remove_indices(png: &mut Png, indices: &[usize]) -> usize {
+    let mut index = 0;
+    png.retain_chunks(|_| {
+        index += 1;
+        !indices.contains(&(index - 1))
+    })
+}
+
+fn read_key<K: FromStr<Err = Error>>(file_path: &String) -> Result<K> {
+    K::from_str(&fs::read_to_string(Path::new(file_path))?)
+}
+
+// @notice: Reads all of the file at file_path, or stdin when it's `-`
+fn read_input(file_path: &str) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    match file_path {
+        "-" => io::stdin().lock().read_to_end(&mut data)?,
+        _ => File::open(Path::new(file_path))?.read_to_end(&mut data)?,
+    };
+    Ok(data)
+}
 
-    let mut buf: Vec<u8> = Vec::new();
-    file.read_to_end(&mut buf).unwrap();
-    let png = Png::try_from(buf.as_ref())?;
+// @notice: Writes data to the file at file_path, or stdout when it's `-`
+fn write_output(file_path: &str, data: &[u8]) -> Result<()> {
+    match file_path {
+        "-" => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(data)?;
+            stdout.flush()?;
+        }
+        _ => fs::write(Path::new(file_path), data)?,
+    }
+    Ok(())
+}
 
-    Ok(png)
+// @notice: Creates a file only its owner can read, for secret keys
+fn create_secret_file(file_path: &str) -> Result<File> {
+    let mut options = File::options();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    Ok(options.open(Path::new(file_path))?)
 }
 
-fn overwrite_file(file_path: &String, buf: &[u8]) -> Result<(), Error> {
-    let mut file = File::options()
+fn overwrite_file(file_path: &String, png: &Png) -> Result<()> {
+    let file = File::options()
         .truncate(true)
         .write(true)
+        .create(true)
         .open(Path::new(file_path))?;
-    file.write_all(buf).unwrap();
+    let mut writer = BufWriter::new(file);
+    png.write_to(&mut writer)?;
+    writer.flush()?;
 
     Ok(())
 }
 
-pub fn execute_command(command: CliCommand) -> Result<(), Box<dyn std::error::Error>> {
+pub fn execute_command(command: CliCommand) -> Result<Output> {
     use CliCommand::*;
 
-    match command {
-        Decode { file_path } => {
-            let png = get_png(&file_path).unwrap();
+    let output = match command {
+        Decode {
+            file_path,
+            chunk_type,
+            passphrase,
+            identity,
+            out,
+            name,
+            method,
+        } => {
+            let (chunk_type, data, limits) = match method {
+                Method::Chunk => {
+                    let png = read_message_chunks(&file_path, &chunk_type)?;
+                    (
+                        chunk_type.to_string(),
+                        find_message(&png, &chunk_type, name.as_deref())?.data?,
+                        png.inflate_limits(),
+                    )
+                }
+                Method::Lsb => {
+                    let png = get_png(&file_path)?;
+                    let data = lsb::extract(&png)?;
+                    (
+                        String::from("IDAT"),
+                        unlabel_named(data, name.as_deref())?,
+                        png.inflate_limits(),
+                    )
+                }
+                Method::Append => {
+                    let png = get_png(&file_path)?;
+                    let data = append::extract(&png)?;
+                    (
+                        String::from("IEND"),
+                        unlabel_named(data, name.as_deref())?,
+                        png.inflate_limits(),
+                    )
+                }
+            };
+
+            let payload = match is_encrypted(&data) {
+                true => {
+                    let identity = identity.as_ref().map(read_key::<Identity>).transpose()?;
+                    let plaintext = decrypt(&data, passphrase.as_deref(), identity.as_ref())?;
+                    Payload::from_bytes_limited(&plaintext, limits)?
+                }
+                false => Payload::from_bytes_limited(&data, limits)?,
+            };
+
+            let message = match &out {
+                Some(output_file) => {
+                    write_output(output_file, &payload.data)?;
+                    None
+                }
+                None => match String::from_utf8(payload.data) {
+                    Ok(message) => Some(message),
+                    Err(_) if payload.file.is_some() => {
+                        return Err(Error::InvalidPayload(String::from(
+                            "an embedded file isn't text, use --out to save it",
+                        )))
+                    }
+                    Err(why) => return Err(why.into()),
+                },
+            };
 
-            if let Some(chunk) = png.chunk_by_type(DEFAULT_CHUNK_TYPE) {
-                println!("{}", chunk.data_as_string().unwrap());
-            } else {
-                println!("{} No message hidden in {file_path}", "Error:".red().bold())
+            Output::Decode {
+                file_path,
+                chunk_type,
+                message,
+                output_file: out,
+                file: payload.file,
             }
         }
         Encode {
             file_path,
             message,
             output_file,
+            output,
+            file,
+            chunk_type,
+            encrypt,
+            passphrase,
+            recipient,
+            compress,
+            max_chunk_size,
+            name,
+            method,
         } => {
-            let mut png =
-                get_png(&file_path).unwrap_or_else(|_| panic!("File {} not found", &file_path));
+            let mut png = get_png(&file_path)?;
+            if name.is_some() && method == Method::Chunk {
+                let replaced: Vec<usize> = messages(&png, Some(&chunk_type))
+                    .iter()
+                    .filter(|message| message.name == name)
+                    .flat_map(|message| message.indices.clone())
+                    .collect();
+                remove_indices(&mut png, &replaced);
+            }
+            let mut payload = match (message, file) {
+                (_, Some(file)) if file == "-" => Payload::from_file("", read_input(&file)?),
+                (_, Some(file)) => Payload::from_file(&file, read_input(&file)?),
+                (Some(message), None) if message == "-" => Payload::new(read_input(&message)?),
+                (Some(message), None) => Payload::new(message.into_bytes()),
+                (None, None) => {
+                    return Err(Error::InvalidPayload(String::from(
+                        "encode needs a message or --file",
+                    )))
+                }
+            };
+            payload.compressed = compress;
+            let payload = payload.to_bytes()?;
 
-            let new_chunk = Chunk::new(
-                ChunkType::new(DEFAULT_CHUNK_TYPE.as_bytes().try_into().unwrap()),
-                message.as_bytes().to_vec(),
-            );
-            png.append_chunk(new_chunk);
-            let buf = png.as_bytes();
+            let recipients = recipient
+                .iter()
+                .map(read_key::<Recipient>)
+                .collect::<Result<Vec<Recipient>>>()?;
+            let data = match (encrypt, passphrase) {
+                (true, Some(passphrase)) => encrypt_with_passphrase(&payload, &passphrase)?,
+                (true, None) => return Err(Error::PassphraseRequired),
+                (false, _) if !recipients.is_empty() => {
+                    encrypt_to_recipients(&payload, &recipients)?
+                }
+                (false, _) => payload,
+            };
+            let data = label(name.as_deref(), data)?;
+            let chunk_type = match method {
+                Method::Chunk => {
+                    for piece in split(&data, max_chunk_size)? {
+                        png.insert_chunk(
+                            Chunk::new(chunk_type.clone(), piece),
+                            InsertPosition::BeforeIend,
+                        );
+                    }
+                    chunk_type.to_string()
+                }
+                Method::Lsb => {
+                    lsb::embed(&mut png, &data)?;
+                    String::from("IDAT")
+                }
+                Method::Append => {
+                    append::embed(&mut png, &data)?;
+                    String::from("IEND")
+                }
+            };
 
-            match output_file {
-                None => overwrite_file(&file_path, buf.as_ref())?,
-                Some(x) => overwrite_file(&x, buf.as_ref())?,
-            }
+            let output_file = output_file.or(output).unwrap_or_else(|| file_path.clone());
+            overwrite_file(&output_file, &png)?;
 
-            println!(
-                "{} Wrote message to '{}'",
-                "SUCCESS:".bright_green().bold(),
-                file_path.blue(),
-            );
+            Output::Encode {
+                file_path,
+                output_file,
+                chunk_type,
+            }
         }
-        Remove { file_path } => {
+        Remove {
+            file_path,
+            chunk_type,
+            name,
+            all,
+            method,
+        } => {
             let mut png = get_png(&file_path)?;
-            png.remove_chunk(DEFAULT_CHUNK_TYPE).unwrap();
-            overwrite_file(&file_path, png.as_bytes().as_ref()).unwrap();
+            let (chunk_type, removed) = match (method, all) {
+                (Method::Chunk, true) => {
+                    let removed = messages(&png, Some(&chunk_type)).len();
+                    png.retain_chunks(|chunk| chunk.chunk_type() != &chunk_type);
+                    if removed == 0 {
+                        return Err(Error::ChunkNotFound(chunk_type.to_string()));
+                    }
+                    (chunk_type.to_string(), removed)
+                }
+                (Method::Chunk, false) => {
+                    let message = find_message(&png, &chunk_type, name.as_deref())?;
+                    remove_indices(&mut png, &message.indices);
+                    (chunk_type.to_string(), 1)
+                }
+                (Method::Append, _) => {
+                    unlabel_named(append::extract(&png)?, name.as_deref())?;
+                    append::remove(&mut png);
+                    (String::from("IEND"), 1)
+                }
+                (Method::Lsb, _) => {
+                    return Err(Error::InvalidPayload(String::from(
+                        "messages hidden with --method lsb can't be removed, encode over them",
+                    )))
+                }
+            };
+            overwrite_file(&file_path, &png)?;
+
+            Output::Remove {
+                file_path,
+                chunk_type,
+                removed,
+            }
+        }
+        List {
+            file_path,
+            chunk_type,
+        } => {
+            let png = get_png(&file_path)?;
+
+            Output::List {
+                file_path,
+                messages: messages(&png, chunk_type.as_ref())
+                    .iter()
+                    .map(|message| MessageInfo::new(message, &png))
+                    .collect(),
+            }
         }
         Print { file_path } => {
             let png = get_png(&file_path)?;
-            println!("{}", png);
+
+            Output::Print {
+                file_path,
+                chunks: chunk_infos(&png),
+                dump: png.to_string(),
+            }
+        }
+        Keygen {
+            identity_file,
+            signing,
+        } => {
+            let (secret, public) = match signing {
+                true => {
+                    let signer = Signer::generate();
+                    (signer.to_string(), signer.verifier().to_string())
+                }
+                false => {
+                    let identity = Identity::generate();
+                    (identity.to_string(), identity.recipient().to_string())
+                }
+            };
+
+            let public_file = format!("{}.pub", identity_file);
+            let mut file = File::options()
+                .write(true)
+                .create_new(true)
+                .open(Path::new(&public_file))?;
+            let mut secret_file = match create_secret_file(&identity_file) {
+                Ok(secret_file) => secret_file,
+                Err(why) => {
+                    drop(file);
+                    fs::remove_file(Path::new(&public_file))?;
+                    return Err(why);
+                }
+            };
+            writeln!(file, "{}", public)?;
+            writeln!(secret_file, "# public key: {}", public)?;
+            writeln!(secret_file, "{}", secret)?;
+
+            Output::Keygen {
+                identity_file,
+                public_file,
+                public_key: public,
+            }
+        }
+        Sign {
+            file_path,
+            key,
+            chunk_type,
+            cover,
+            detached,
+        } => {
+            let mut png = get_png(&file_path)?;
+            let signer = read_key::<Signer>(&key)?;
+
+            while png.remove_chunk(signature::CHUNK_TYPE).is_ok() {}
+            let covered_types = match cover.is_empty() {
+                true => default_covered_types(&png, &chunk_type),
+                false => cover,
+            };
+            let data = signature::sign(&png, &signer, &covered_types)?;
+
+            match &detached {
+                Some(signature_file) => fs::write(Path::new(signature_file), data)?,
+                None => {
+                    let chunk_type = ChunkType::from_str(signature::CHUNK_TYPE)?;
+                    png.insert_chunk(Chunk::new(chunk_type, data), InsertPosition::BeforeIend);
+                    overwrite_file(&file_path, &png)?;
+                }
+            }
+
+            Output::Sign {
+                file_path,
+                signature_file: detached,
+                covered_types: covered_types.iter().map(|t| t.to_string()).collect(),
+                public_key: signer.verifier().to_string(),
+            }
+        }
+        Verify {
+            file_path,
+            key,
+            signature: signature_file,
+        } => {
+            let png = get_png(&file_path)?;
+            let data = match &signature_file {
+                Some(signature_file) => fs::read(Path::new(signature_file))?,
+                None => png
+                    .chunk_by_type(signature::CHUNK_TYPE)
+                    .ok_or_else(|| Error::ChunkNotFound(String::from(signature::CHUNK_TYPE)))?
+                    .data()
+                    .to_vec(),
+            };
+
+            let verification = signature::verify(&png, &data)?;
+            let trusted = read_key::<Verifier>(&key)? == verification.signer;
+
+            Output::Verify {
+                file_path,
+                valid: verification.is_valid(),
+                signature_valid: verification.signature_valid,
+                trusted,
+                signer: verification.signer.to_string(),
+                chunks: verification.chunks,
+            }
+        }
+        Info { file_path } => {
+            let png = get_png(&file_path)?;
+
+            Output::Info {
+                file_path,
+                chunks: chunk_infos(&png),
+                trailing_data: png.trailing_data().len(),
+            }
+        }
+        Repair {
+            file_path,
+            output_file,
+        } => {
+            let file = File::open(Path::new(&file_path))?;
+            let (mut png, damage) = Png::from_reader_lenient(BufReader::new(file))?;
+            let fixes = repair(&mut png, damage);
+
+            let output_file = match fixes.is_empty() {
+                true => None,
+                false => {
+                    let output_file = output_file.unwrap_or_else(|| file_path.clone());
+                    overwrite_file(&output_file, &png)?;
+                    Some(output_file)
+                }
+            };
+            Output::Repair {
+                file_path,
+                output_file,
+                fixes,
+            }
+        }
+        Check { file_path } => {
+            let png = get_png(&file_path)?;
+            let diagnostics = png.validate();
+            let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+
+            Output::Check {
+                file_path,
+                errors,
+                warnings: diagnostics.len() - errors,
+                diagnostics,
+            }
         }
     };
 
-    Ok(())
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_message_chunks() {
+        let path = std::env::temp_dir().join(format!("pngme-stream-{}.png", std::process::id()));
+        let mut png = get_png(&String::from("testing/test.png")).unwrap();
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        for piece in split(&[7; 100], 40).unwrap() {
+            png.insert_chunk(
+                Chunk::new(chunk_type.clone(), piece),
+                InsertPosition::BeforeIend,
+            );
+        }
+        let file_path = path.to_string_lossy().into_owned();
+        overwrite_file(&file_path, &png).unwrap();
+
+        let result = read_message_chunks(&file_path, &chunk_type);
+        fs::remove_file(&path).unwrap();
+        let chunks = result.unwrap();
+        assert!(chunks.chunks().len() > 1);
+        assert!(chunks
+            .chunks()
+            .iter()
+            .all(|c| c.chunk_type() == &chunk_type));
+        let message = find_message(&chunks, &chunk_type, None).unwrap();
+        assert_eq!(message.data.unwrap(), [7; 100]);
+    }
+
+    #[test]
+    fn test_read_legacy_message_chunks() {
+        let path = std::env::temp_dir().join(format!("pngme-legacy-{}.png", std::process::id()));
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        let mut bytes = fs::read("testing/test.png").unwrap();
+        bytes.extend_from_slice(&Chunk::new(chunk_type.clone(), b"Old".to_vec()).as_bytes());
+        bytes.extend_from_slice(b"PK appended zip");
+        fs::write(&path, &bytes).unwrap();
+        let file_path = path.to_string_lossy().into_owned();
+
+        let chunks = read_message_chunks(&file_path, &chunk_type);
+        let png = get_png(&file_path);
+        fs::remove_file(&path).unwrap();
+        let chunks = chunks.unwrap();
+        assert_eq!(chunks.chunks().len(), 1);
+        assert_eq!(chunks.chunks()[0].data(), b"Old");
+        let png = png.unwrap();
+        assert!(png.chunk_by_type("ruSt").is_none());
+        assert!(png.trailing_data().ends_with(b"PK appended zip"));
+    }
+
+    #[test]
+    fn test_encode_without_message() {
+        let path = std::env::temp_dir().join(format!("pngme-encode-{}.png", std::process::id()));
+        fs::copy("testing/test.png", &path).unwrap();
+        let file_path = path.to_string_lossy().into_owned();
+
+        let result = execute_command(CliCommand::Encode {
+            file_path,
+            message: None,
+            output_file: None,
+            output: None,
+            file: None,
+            chunk_type: ChunkType::from_str("ruSt").unwrap(),
+            encrypt: false,
+            passphrase: None,
+            recipient: Vec::new(),
+            compress: false,
+            max_chunk_size: crate::fragment::DEFAULT_MAXIMUM_SIZE,
+            name: None,
+            method: Method::Chunk,
+        });
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(Error::InvalidPayload(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keygen_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("pngme-keygen-{}.key", std::process::id()));
+        let identity_file = path.to_string_lossy().into_owned();
+        let public_file = format!("{}.pub", identity_file);
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&public_file);
+
+        execute_command(CliCommand::Keygen {
+            identity_file: identity_file.clone(),
+            signing: false,
+        })
+        .unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        let identity = read_key::<Identity>(&identity_file);
+        let recipient = read_key::<Recipient>(&public_file);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&public_file).unwrap();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(identity.unwrap().recipient(), recipient.unwrap());
+    }
 }
@@ -0,0 +1,590 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+
+use crate::error::{Error, Result};
+
+/// Marks chunk data as an encrypted envelope. 0x89 can't start UTF-8 text, so plain
+/// messages are never mistaken for one.
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'E'];
+const VERSION: u8 = 1;
+
+/// Key derived from a passphrase with Argon2id
+const KIND_PASSPHRASE: u8 = 1;
+/// Random file key wrapped for each X25519 recipient
+const KIND_RECIPIENTS: u8 = 2;
+
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32;
+const TAG_LENGTH: usize = 16;
+
+/// Domain separation for the key wrapping a file key for one recipient
+const WRAP_KEY_INFO: &[u8] = b"pngme/x25519/v1";
+const PUBLIC_KEY_PREFIX: &str = "pngme-public-";
+const SECRET_KEY_PREFIX: &str = "pngme-secret-";
+
+/// How many times the costs new envelopes use an envelope's Argon2 costs may be, so a
+/// crafted file can't stall decoding or claim gigabytes of memory
+const MAXIMUM_COST_FACTOR: u32 = 4;
+
+/// Argon2id costs used for new envelopes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub memory_cost: u32,
+    pub time_cost: u32,
+    pub parallelism: u8,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: 1,
+        }
+    }
+}
+
+/// How an envelope's key is obtained
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeKind {
+    Passphrase,
+    Recipients,
+}
+
+/// A public key messages can be encrypted to
+#[derive(Clone, PartialEq, Eq)]
+pub struct Recipient(PublicKey);
+
+/// A secret key that opens messages encrypted to its `Recipient`
+#[derive(Clone)]
+pub struct Identity(StaticSecret);
+
+impl Identity {
+    pub fn generate() -> Identity {
+        Identity(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn recipient(&self) -> Recipient {
+        Recipient(PublicKey::from(&self.0))
+    }
+}
+
+impl Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, to_hex(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Recipient({})", self)
+    }
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SECRET_KEY_PREFIX, to_hex(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for Recipient {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = parse_key(s, PUBLIC_KEY_PREFIX)?;
+        Ok(Recipient(PublicKey::from(bytes)))
+    }
+}
+
+impl FromStr for Identity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = parse_key(s, SECRET_KEY_PREFIX)?;
+        Ok(Identity(StaticSecret::from(bytes)))
+    }
+}
+
+/// Whether `data` starts like an encrypted envelope
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// The kind of envelope `data` holds, or `None` when it isn't one
+pub fn envelope_kind(data: &[u8]) -> Option<EnvelopeKind> {
+    if !is_encrypted(data) || data.get(MAGIC.len()) != Some(&VERSION) {
+        return None;
+    }
+    match data.get(MAGIC.len() + 1) {
+        Some(&KIND_PASSPHRASE) => Some(EnvelopeKind::Passphrase),
+        Some(&KIND_RECIPIENTS) => Some(EnvelopeKind::Recipients),
+        _ => None,
+    }
+}
+
+/// Opens any envelope with whichever secret its kind needs
+pub fn decrypt(
+    envelope: &[u8],
+    passphrase: Option<&str>,
+    identity: Option<&Identity>,
+) -> Result<Vec<u8>> {
+    match (envelope_kind(envelope), passphrase, identity) {
+        (Some(EnvelopeKind::Passphrase), Some(passphrase), _) => {
+            decrypt_with_passphrase(envelope, passphrase)
+        }
+        (Some(EnvelopeKind::Passphrase), None, _) => Err(Error::PassphraseRequired),
+        (Some(EnvelopeKind::Recipients), _, Some(identity)) => {
+            decrypt_with_identity(envelope, identity)
+        }
+        (Some(EnvelopeKind::Recipients), _, None) => Err(Error::IdentityRequired),
+        (None, _, _) => Err(invalid("unsupported version or kind")),
+    }
+}
+
+/// Encrypts `plaintext` with a key derived from `passphrase`, using the default KDF costs
+pub fn encrypt_with_passphrase(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    encrypt_with_params(plaintext, passphrase, KdfParams::default())
+}
+
+/// Encrypts `plaintext` into a versioned envelope:
+/// magic, version, kind, Argon2 costs, salt, nonce, then the ChaCha20-Poly1305 ciphertext.
+/// Everything before the ciphertext is authenticated too.
+pub fn encrypt_with_params(
+    plaintext: &[u8],
+    passphrase: &str,
+    params: KdfParams,
+) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut envelope = Vec::new();
+    envelope.extend_from_slice(&MAGIC);
+    envelope.push(VERSION);
+    envelope.push(KIND_PASSPHRASE);
+    envelope.extend_from_slice(&params.memory_cost.to_be_bytes());
+    envelope.extend_from_slice(&params.time_cost.to_be_bytes());
+    envelope.push(params.parallelism);
+    envelope.extend_from_slice(&salt);
+    envelope.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, params)?;
+    let ciphertext = ChaCha20Poly1305::new(&key)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &envelope,
+            },
+        )
+        .map_err(|_| Error::Encryption(String::from("cipher failure")))?;
+
+    envelope.extend(ciphertext);
+    Ok(envelope)
+}
+
+/// Opens an envelope made by `encrypt_with_passphrase`, failing on a wrong passphrase or any
+/// modification of the envelope
+pub fn decrypt_with_passphrase(envelope: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::open(envelope, KIND_PASSPHRASE)?;
+
+    let params = KdfParams {
+        memory_cost: cursor.u32()?,
+        time_cost: cursor.u32()?,
+        parallelism: cursor.byte()?,
+    };
+    let ceiling = KdfParams::default();
+    if params.memory_cost > ceiling.memory_cost * MAXIMUM_COST_FACTOR
+        || params.time_cost > ceiling.time_cost * MAXIMUM_COST_FACTOR
+        || u32::from(params.parallelism) > u32::from(ceiling.parallelism) * MAXIMUM_COST_FACTOR
+    {
+        return Err(invalid("key derivation costs are too high"));
+    }
+    let salt = cursor.take(SALT_LENGTH)?;
+    let nonce = cursor.take(NONCE_LENGTH)?;
+    let header_length = cursor.position;
+
+    let key = derive_key(passphrase, salt, params)?;
+    ChaCha20Poly1305::new(&key)
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: cursor.rest(),
+                aad: &envelope[..header_length],
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+/// Encrypts `plaintext` so any one of `recipients` can open it, age-style: a random file key
+/// encrypts the message and is wrapped once per recipient with a key agreed through an
+/// ephemeral X25519 exchange. The envelope holds magic, version, kind, the recipient count,
+/// one stanza (ephemeral public key, wrapped file key) per recipient, a nonce and the
+/// ciphertext, with everything before the ciphertext authenticated.
+pub fn encrypt_to_recipients(plaintext: &[u8], recipients: &[Recipient]) -> Result<Vec<u8>> {
+    let count = u8::try_from(recipients.len())
+        .ok()
+        .filter(|&count| count > 0)
+        .ok_or_else(|| Error::Encryption(String::from("need between 1 and 255 recipients")))?;
+
+    let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut nonce = [0u8; NONCE_LENGTH];
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut envelope = Vec::new();
+    envelope.extend_from_slice(&MAGIC);
+    envelope.push(VERSION);
+    envelope.push(KIND_RECIPIENTS);
+    envelope.push(count);
+    for recipient in recipients {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_public = PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&recipient.0);
+
+        let wrap_key = derive_wrap_key(shared.as_bytes(), &ephemeral_public, &recipient.0)?;
+        let wrapped = ChaCha20Poly1305::new(&wrap_key)
+            .encrypt(&Nonce::default(), file_key.as_slice())
+            .map_err(|_| Error::Encryption(String::from("cipher failure")))?;
+
+        envelope.extend_from_slice(ephemeral_public.as_bytes());
+        envelope.extend(wrapped);
+    }
+    envelope.extend_from_slice(&nonce);
+
+    let ciphertext = ChaCha20Poly1305::new(&file_key)
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &envelope,
+            },
+        )
+        .map_err(|_| Error::Encryption(String::from("cipher failure")))?;
+
+    envelope.extend(ciphertext);
+    Ok(envelope)
+}
+
+/// Opens an envelope made by `encrypt_to_recipients` with one recipient's identity
+pub fn decrypt_with_identity(envelope: &[u8], identity: &Identity) -> Result<Vec<u8>> {
+    let mut cursor = Cursor::open(envelope, KIND_RECIPIENTS)?;
+    let public = PublicKey::from(&identity.0);
+
+    let mut file_key = None;
+    for _ in 0..cursor.byte()? {
+        let ephemeral_public = PublicKey::from(cursor.array::<KEY_LENGTH>()?);
+        let wrapped = cursor.take(KEY_LENGTH + TAG_LENGTH)?;
+        if file_key.is_some() {
+            continue;
+        }
+
+        let shared = identity.0.diffie_hellman(&ephemeral_public);
+        let wrap_key = derive_wrap_key(shared.as_bytes(), &ephemeral_public, &public)?;
+        file_key = ChaCha20Poly1305::new(&wrap_key)
+            .decrypt(&Nonce::default(), wrapped)
+            .ok();
+    }
+    let file_key = file_key.ok_or(Error::NoMatchingRecipient)?;
+    if file_key.len() != KEY_LENGTH {
+        return Err(invalid("wrapped file key has the wrong length"));
+    }
+
+    let nonce = cursor.take(NONCE_LENGTH)?;
+    let header_length = cursor.position;
+    ChaCha20Poly1305::new(Key::from_slice(&file_key))
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: cursor.rest(),
+                aad: &envelope[..header_length],
+            },
+        )
+        .map_err(|_| Error::Decryption)
+}
+
+fn derive_wrap_key(shared: &[u8], ephemeral: &PublicKey, recipient: &PublicKey) -> Result<Key> {
+    let salt = [ephemeral.as_bytes().as_slice(), recipient.as_bytes()].concat();
+    let mut key = Key::default();
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_KEY_INFO, &mut key)
+        .map_err(|why| Error::Encryption(why.to_string()))?;
+    Ok(key)
+}
+
+pub(crate) fn parse_key(s: &str, prefix: &str) -> Result<[u8; KEY_LENGTH]> {
+    let line = s
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .unwrap_or("");
+    line.strip_prefix(prefix)
+        .and_then(from_hex)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| Error::InvalidKey(format!("expected a line starting with {}", prefix)))
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<Key> {
+    let params = Params::new(
+        params.memory_cost,
+        params.time_cost,
+        u32::from(params.parallelism),
+        Some(KEY_LENGTH),
+    )
+    .map_err(|why| Error::Encryption(why.to_string()))?;
+
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|why| Error::Encryption(why.to_string()))?;
+    Ok(key)
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidEnvelope(String::from(reason))
+}
+
+/// Reads envelope fields front to back
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    /// Checks the magic, version and kind, leaving the cursor at the kind-specific fields
+    fn open(bytes: &'a [u8], kind: u8) -> Result<Cursor<'a>> {
+        let mut cursor = Cursor { bytes, position: 0 };
+        if cursor.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("missing magic bytes"));
+        }
+        let version = cursor.byte()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let found = cursor.byte()?;
+        if found != kind {
+            return Err(invalid(&format!("unsupported kind {}", found)));
+        }
+        Ok(cursor)
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let slice = self
+            .bytes
+            .get(self.position..self.position + length)
+            .ok_or_else(|| invalid("envelope is truncated"))?;
+        self.position += length;
+        Ok(slice)
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.array()?))
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.position..]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap costs so the tests stay fast in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = encrypt_with_params(b"Secret message", "hunter2", TEST_PARAMS).unwrap();
+        assert!(is_encrypted(&envelope));
+        assert!(!envelope
+            .windows(b"Secret".len())
+            .any(|window| window == b"Secret"));
+        assert_eq!(
+            decrypt_with_passphrase(&envelope, "hunter2").unwrap(),
+            b"Secret message"
+        );
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let envelope = encrypt_with_params(b"Secret message", "hunter2", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            decrypt_with_passphrase(&envelope, "hunter3"),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn test_tampered_envelope() {
+        let envelope = encrypt_with_params(b"Secret message", "hunter2", TEST_PARAMS).unwrap();
+
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(
+            decrypt_with_passphrase(&tampered, "hunter2"),
+            Err(Error::Decryption)
+        ));
+
+        // The salt is part of the authenticated header
+        let mut tampered = envelope.clone();
+        tampered[20] ^= 1;
+        assert!(decrypt_with_passphrase(&tampered, "hunter2").is_err());
+
+        assert!(matches!(
+            decrypt_with_passphrase(&envelope[..30], "hunter2"),
+            Err(Error::InvalidEnvelope(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_expensive_params() {
+        let defaults = KdfParams::default();
+        let expensive = [
+            KdfParams {
+                memory_cost: u32::MAX,
+                ..TEST_PARAMS
+            },
+            KdfParams {
+                memory_cost: defaults.memory_cost * MAXIMUM_COST_FACTOR + 1,
+                ..TEST_PARAMS
+            },
+            KdfParams {
+                time_cost: defaults.time_cost * MAXIMUM_COST_FACTOR + 1,
+                ..TEST_PARAMS
+            },
+            KdfParams {
+                parallelism: 5,
+                ..TEST_PARAMS
+            },
+        ];
+        let envelope = encrypt_with_params(b"Secret", "hunter2", TEST_PARAMS).unwrap();
+        for params in expensive {
+            let mut envelope = envelope.clone();
+            envelope[6..10].copy_from_slice(&params.memory_cost.to_be_bytes());
+            envelope[10..14].copy_from_slice(&params.time_cost.to_be_bytes());
+            envelope[14] = params.parallelism;
+            assert!(matches!(
+                decrypt_with_passphrase(&envelope, "hunter2"),
+                Err(Error::InvalidEnvelope(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_plain_text_is_not_encrypted() {
+        assert!(!is_encrypted(b"Hello"));
+        assert!(!is_encrypted(b""));
+        assert_eq!(envelope_kind(b"Hello"), None);
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let alice = Identity::generate();
+        let bob = Identity::generate();
+        let eve = Identity::generate();
+
+        let envelope =
+            encrypt_to_recipients(b"Team message", &[alice.recipient(), bob.recipient()]).unwrap();
+        assert_eq!(envelope_kind(&envelope), Some(EnvelopeKind::Recipients));
+
+        for identity in [&alice, &bob] {
+            assert_eq!(
+                decrypt_with_identity(&envelope, identity).unwrap(),
+                b"Team message"
+            );
+        }
+        assert!(matches!(
+            decrypt_with_identity(&envelope, &eve),
+            Err(Error::NoMatchingRecipient)
+        ));
+    }
+
+    #[test]
+    fn test_recipients_tampered() {
+        let alice = Identity::generate();
+        let mut envelope = encrypt_to_recipients(b"Team message", &[alice.recipient()]).unwrap();
+
+        // Flip a bit of the nonce, which is authenticated
+        envelope[7 + 80] ^= 1;
+        assert!(matches!(
+            decrypt_with_identity(&envelope, &alice),
+            Err(Error::Decryption)
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_dispatches_on_kind() {
+        let alice = Identity::generate();
+        let envelope = encrypt_to_recipients(b"Team message", &[alice.recipient()]).unwrap();
+        assert!(matches!(
+            decrypt(&envelope, Some("hunter2"), None),
+            Err(Error::IdentityRequired)
+        ));
+        assert_eq!(
+            decrypt(&envelope, None, Some(&alice)).unwrap(),
+            b"Team message"
+        );
+
+        let envelope = encrypt_with_params(b"Secret", "hunter2", TEST_PARAMS).unwrap();
+        assert!(matches!(
+            decrypt(&envelope, None, Some(&alice)),
+            Err(Error::PassphraseRequired)
+        ));
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        let identity = Identity::generate();
+        let file = format!("# public key: {}\n{}\n", identity.recipient(), identity);
+
+        let parsed = Identity::from_str(&file).unwrap();
+        assert_eq!(parsed.recipient(), identity.recipient());
+        assert_eq!(
+            Recipient::from_str(&identity.recipient().to_string()).unwrap(),
+            identity.recipient()
+        );
+
+        // An identity isn't a recipient and vice versa
+        assert!(Recipient::from_str(&identity.to_string()).is_err());
+        assert!(Identity::from_str(&identity.recipient().to_string()).is_err());
+        assert!(Recipient::from_str("pngme-public-abcd").is_err());
+    }
+}
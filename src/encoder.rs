@@ -0,0 +1,183 @@
+use flate2::Compression;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::filter::FilterType;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::image::Image;
+use crate::png::Png;
+use crate::zlib::deflate_with;
+
+/// Largest IDAT chunk written unless told otherwise, the size libpng uses
+pub const DEFAULT_IDAT_SIZE: usize = 8192;
+
+/// How rows are filtered before compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// Every row uses the same filter
+    Fixed(FilterType),
+    /// Each row gets the filter that's likely to compress best, or none for indexed and
+    /// sub-byte images as the spec suggests
+    Adaptive,
+}
+
+/// Knobs for `encode`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub filter: FilterStrategy,
+    /// zlib level from 0 (store) to 9 (smallest), higher levels are treated as 9
+    pub compression_level: u32,
+    /// Largest IDAT chunk written, the compressed data is split across as many as needed
+    pub idat_size: usize,
+    /// RGB entries written as PLTE, required for indexed images and refused for greyscale ones
+    pub palette: Option<Vec<[u8; 3]>>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            filter: FilterStrategy::Adaptive,
+            compression_level: Compression::default().level(),
+            idat_size: DEFAULT_IDAT_SIZE,
+            palette: None,
+        }
+    }
+}
+
+/// Builds a complete PNG from `pixels`, laid out as in `Image`, following `ihdr`'s
+/// dimensions, format and interlacing
+pub fn encode(ihdr: &Ihdr, pixels: &[u8], options: &EncodeOptions) -> Result<Png> {
+    let image = Image::new(
+        ihdr.width,
+        ihdr.height,
+        ihdr.color_type,
+        ihdr.bit_depth,
+        pixels.to_vec(),
+    )?;
+    let mut scanlines = image.to_scanlines(ihdr.interlace_method)?;
+    match options.filter {
+        FilterStrategy::Fixed(filter) => scanlines.filters.fill(filter),
+        FilterStrategy::Adaptive if ihdr.color_type == ColorType::Indexed || ihdr.bit_depth < 8 => {
+        }
+        FilterStrategy::Adaptive => scanlines.select_filters(),
+    }
+    let level = Compression::new(options.compression_level.min(9));
+    let compressed = deflate_with(&scanlines.filter(), level)?;
+
+    let mut chunks = vec![ihdr.to_chunk()];
+    match (&options.palette, ihdr.color_type) {
+        (Some(_), ColorType::Grayscale | ColorType::GrayscaleAlpha) => {
+            return Err(Error::UnsupportedImage(String::from(
+                "greyscale images can't have a palette",
+            )))
+        }
+        (Some(palette), _) => chunks.push(palette_chunk(palette, ihdr.bit_depth)?),
+        (None, ColorType::Indexed) => {
+            return Err(Error::UnsupportedImage(String::from(
+                "indexed images need a palette",
+            )))
+        }
+        (None, _) => {}
+    }
+    for data in compressed.chunks(options.idat_size.max(1)) {
+        chunks.push(Chunk::new(ChunkType::new(b"IDAT"), data.to_vec()));
+    }
+    chunks.push(Chunk::new(ChunkType::new(b"IEND"), Vec::new()));
+    Ok(Png::from_chunks(chunks))
+}
+
+fn palette_chunk(palette: &[[u8; 3]], bit_depth: u8) -> Result<Chunk> {
+    let maximum = 1usize << bit_depth.min(8);
+    if palette.is_empty() || palette.len() > maximum {
+        return Err(Error::UnsupportedImage(format!(
+            "palettes need 1 to {} entries, found {}",
+            maximum,
+            palette.len()
+        )));
+    }
+    Ok(Chunk::new(ChunkType::new(b"PLTE"), palette.concat()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::InterlaceMethod;
+
+    fn gradient(ihdr: &Ihdr) -> Vec<u8> {
+        let samples = ihdr.width * ihdr.height * ihdr.color_type.channels() as u32;
+        (0..samples).map(|i| (i / 3 % 256) as u8).collect()
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        for interlace_method in [InterlaceMethod::None, InterlaceMethod::Adam7] {
+            let ihdr = Ihdr::new(40, 30, 8, ColorType::Rgb, interlace_method).unwrap();
+            let pixels = gradient(&ihdr);
+            let png = encode(&ihdr, &pixels, &EncodeOptions::default()).unwrap();
+
+            let bytes = png.as_bytes();
+            let decoded = Png::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(decoded.header_info().unwrap(), ihdr);
+            assert_eq!(decoded.decode_image().unwrap().pixels, pixels);
+        }
+    }
+
+    #[test]
+    fn test_idat_split_and_compression_level() {
+        let ihdr = Ihdr::new(64, 64, 8, ColorType::Rgba, InterlaceMethod::None).unwrap();
+        let pixels = gradient(&ihdr);
+        let options = EncodeOptions {
+            filter: FilterStrategy::Fixed(FilterType::None),
+            compression_level: 0,
+            idat_size: 1000,
+            palette: None,
+        };
+        let png = encode(&ihdr, &pixels, &options).unwrap();
+        let types = chunk_types(&png);
+        assert!(types.iter().filter(|t| *t == "IDAT").count() > 16);
+        assert!(png.chunks().iter().all(|c| c.length() <= 1000));
+        assert_eq!(png.decode_image().unwrap().pixels, pixels);
+
+        let smaller = encode(&ihdr, &pixels, &EncodeOptions::default()).unwrap();
+        assert!(smaller.image_data().len() < png.image_data().len());
+    }
+
+    #[test]
+    fn test_encode_indexed() {
+        let ihdr = Ihdr::new(5, 2, 2, ColorType::Indexed, InterlaceMethod::None).unwrap();
+        let pixels = vec![0, 1, 2, 3, 0, 3, 2, 1, 0, 1];
+        assert!(matches!(
+            encode(&ihdr, &pixels, &EncodeOptions::default()),
+            Err(Error::UnsupportedImage(_))
+        ));
+
+        let options = EncodeOptions {
+            palette: Some(vec![[0, 0, 0], [255, 0, 0], [0, 255, 0], [0, 0, 255]]),
+            ..EncodeOptions::default()
+        };
+        let png = encode(&ihdr, &pixels, &options).unwrap();
+        assert_eq!(chunk_types(&png), ["IHDR", "PLTE", "IDAT", "IEND"]);
+        assert_eq!(png.decode_image().unwrap().pixels, pixels);
+    }
+
+    #[test]
+    fn test_encode_grayscale_with_palette() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Grayscale, InterlaceMethod::None).unwrap();
+        let options = EncodeOptions {
+            palette: Some(vec![[0, 0, 0], [255, 255, 255]]),
+            ..EncodeOptions::default()
+        };
+        assert!(matches!(
+            encode(&ihdr, &[0, 255, 255, 0], &options),
+            Err(Error::UnsupportedImage(_))
+        ));
+    }
+}
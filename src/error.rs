@@ -1 +1,236 @@
-pub enum Error {}
+use std::fmt::{self, Display};
+use std::io;
+use std::string::FromUtf8Error;
+
+use crate::chunk_type::ChunkType;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Every way reading, editing or writing a PNG can fail
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the underlying file failed
+    Io(io::Error),
+    /// The file doesn't start with the PNG signature
+    BadSignature([u8; 8]),
+    /// The input ended in the middle of a chunk
+    Truncated { offset: usize },
+    /// A chunk's stored CRC doesn't match its contents
+    CrcMismatch {
+        chunk_type: ChunkType,
+        expected: u32,
+        actual: u32,
+        /// Position of the chunk in the file, when it was read from one
+        index: Option<usize>,
+        /// Byte offset of the chunk in the file, when it was read from one
+        offset: Option<usize>,
+    },
+    /// A chunk type isn't made of four ASCII letters
+    InvalidChunkType(String),
+    /// No chunk of the requested type exists
+    ChunkNotFound(String),
+    /// A chunk length is larger than the spec allows
+    LengthOverflow(u64),
+    /// Chunk data isn't valid UTF-8
+    Utf8(FromUtf8Error),
+    /// The IHDR chunk is missing, misplaced or breaks the spec
+    InvalidIhdr(String),
+    /// A tEXt, zTXt or iTXt chunk is malformed or can't be encoded
+    InvalidText(String),
+    /// A zlib stream couldn't be inflated
+    Decompression(String),
+    /// A message couldn't be encrypted
+    Encryption(String),
+    /// An encrypted message failed to authenticate: wrong key or tampered data
+    Decryption,
+    /// An encrypted message's envelope is malformed or unsupported
+    InvalidEnvelope(String),
+    /// The message is encrypted but no passphrase was given
+    PassphraseRequired,
+    /// The message is encrypted to recipients but no identity was given
+    IdentityRequired,
+    /// The given identity isn't one of the message's recipients
+    NoMatchingRecipient,
+    /// A public or secret key couldn't be parsed
+    InvalidKey(String),
+    /// Signature data is malformed or unsupported
+    InvalidSignature(String),
+    /// A framed message payload is malformed or unsupported
+    InvalidPayload(String),
+    /// Image data doesn't match what IHDR describes
+    InvalidImageData(String),
+    /// The image's format can't be used for what was asked
+    UnsupportedImage(String),
+    /// A message doesn't fit in the space available for it
+    CapacityExceeded { needed: usize, available: usize },
+    /// No message has the requested name
+    MessageNotFound(String),
+    /// Fragments of a split payload are missing or repeated
+    BrokenFragments {
+        payload_id: u32,
+        /// The first few missing sequence numbers
+        missing: Vec<u32>,
+        /// How many fragments are missing in all
+        missing_count: u32,
+        duplicated: Vec<u32>,
+    },
+    /// Bytes follow IEND where they weren't allowed
+    TrailingData { offset: usize, length: usize },
+    /// Input goes past a limit set when parsing, `limit` names which
+    LimitExceeded { limit: &'static str, maximum: u64 },
+}
+
+impl Error {
+    /// Stable identifier for the kind of error, used in machine-readable output
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Io(_) => "io",
+            Error::BadSignature(_) => "bad_signature",
+            Error::Truncated { .. } => "truncated",
+            Error::CrcMismatch { .. } => "crc_mismatch",
+            Error::InvalidChunkType(_) => "invalid_chunk_type",
+            Error::ChunkNotFound(_) => "chunk_not_found",
+            Error::LengthOverflow(_) => "length_overflow",
+            Error::Utf8(_) => "utf8",
+            Error::InvalidIhdr(_) => "invalid_ihdr",
+            Error::InvalidText(_) => "invalid_text",
+            Error::Decompression(_) => "decompression",
+            Error::Encryption(_) => "encryption",
+            Error::Decryption => "decryption",
+            Error::InvalidEnvelope(_) => "invalid_envelope",
+            Error::PassphraseRequired => "passphrase_required",
+            Error::IdentityRequired => "identity_required",
+            Error::NoMatchingRecipient => "no_matching_recipient",
+            Error::InvalidKey(_) => "invalid_key",
+            Error::InvalidSignature(_) => "invalid_signature",
+            Error::InvalidPayload(_) => "invalid_payload",
+            Error::InvalidImageData(_) => "invalid_image_data",
+            Error::UnsupportedImage(_) => "unsupported_image",
+            Error::CapacityExceeded { .. } => "capacity_exceeded",
+            Error::MessageNotFound(_) => "message_not_found",
+            Error::BrokenFragments { .. } => "broken_fragments",
+            Error::TrailingData { .. } => "trailing_data",
+            Error::LimitExceeded { .. } => "limit_exceeded",
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(why) => write!(f, "IO error: {}", why),
+            Error::BadSignature(header) => write!(f, "Bad PNG signature {:?}", header),
+            Error::Truncated { offset } => write!(f, "Unexpected end of data at byte {}", offset),
+            Error::CrcMismatch {
+                chunk_type,
+                expected,
+                actual,
+                index,
+                offset,
+            } => {
+                write!(f, "CRC mismatch in {} chunk", chunk_type)?;
+                if let Some(index) = index {
+                    write!(f, " {}", index)?;
+                }
+                if let Some(offset) = offset {
+                    write!(f, " at byte {:#x}", offset)?;
+                }
+                write!(f, " (expected {:#010x}, got {:#010x})", expected, actual)
+            }
+            Error::InvalidChunkType(chunk_type) => {
+                write!(f, "Invalid chunk type {:?}", chunk_type)
+            }
+            Error::ChunkNotFound(chunk_type) => {
+                write!(f, "Couldn't find chunk of type {}", chunk_type)
+            }
+            Error::LengthOverflow(length) => write!(f, "Chunk length {} is too long", length),
+            Error::Utf8(why) => write!(f, "Chunk data isn't valid UTF-8: {}", why),
+            Error::InvalidIhdr(reason) => write!(f, "Bad IHDR chunk: {}", reason),
+            Error::InvalidText(reason) => write!(f, "Bad text chunk: {}", reason),
+            Error::Decompression(reason) => write!(f, "Couldn't inflate data: {}", reason),
+            Error::Encryption(reason) => write!(f, "Couldn't encrypt message: {}", reason),
+            Error::Decryption => write!(
+                f,
+                "Couldn't decrypt message: wrong passphrase or tampered data"
+            ),
+            Error::InvalidEnvelope(reason) => write!(f, "Bad encrypted message: {}", reason),
+            Error::PassphraseRequired => {
+                write!(f, "Message is encrypted, pass --passphrase to decode it")
+            }
+            Error::IdentityRequired => write!(
+                f,
+                "Message is encrypted to recipients, pass --identity to decode it"
+            ),
+            Error::NoMatchingRecipient => {
+                write!(f, "Message isn't encrypted to the given identity")
+            }
+            Error::InvalidKey(reason) => write!(f, "Bad key: {}", reason),
+            Error::InvalidSignature(reason) => write!(f, "Bad signature: {}", reason),
+            Error::InvalidPayload(reason) => write!(f, "Bad payload: {}", reason),
+            Error::InvalidImageData(reason) => write!(f, "Bad image data: {}", reason),
+            Error::UnsupportedImage(reason) => write!(f, "Unsupported image: {}", reason),
+            Error::CapacityExceeded { needed, available } => write!(
+                f,
+                "Message needs {} bytes but only {} are available",
+                needed, available
+            ),
+            Error::MessageNotFound(name) => write!(f, "No message named {:?}", name),
+            Error::BrokenFragments {
+                payload_id,
+                missing,
+                missing_count,
+                duplicated,
+            } => {
+                let list = |sequences: &[u32]| {
+                    sequences
+                        .iter()
+                        .map(|s| s.to_string())
+                        .collect::<Vec<String>>()
+                        .join(", ")
+                };
+                write!(f, "Payload {:08x} can't be reassembled:", payload_id)?;
+                if !missing.is_empty() {
+                    write!(f, " missing fragments {}", list(missing))?;
+                }
+                if *missing_count as usize > missing.len() {
+                    write!(f, " and {} more", *missing_count as usize - missing.len())?;
+                }
+                if !missing.is_empty() && !duplicated.is_empty() {
+                    write!(f, ";")?;
+                }
+                if !duplicated.is_empty() {
+                    write!(f, " duplicated fragments {}", list(duplicated))?;
+                }
+                Ok(())
+            }
+            Error::TrailingData { offset, length } => {
+                write!(f, "{} bytes of data follow IEND at byte {}", length, offset)
+            }
+            Error::LimitExceeded { limit, maximum } => {
+                write!(f, "Input exceeds the {} limit of {}", limit, maximum)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(why) => Some(why),
+            Error::Utf8(why) => Some(why),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(why: io::Error) -> Self {
+        Error::Io(why)
+    }
+}
+
+impl From<FromUtf8Error> for Error {
+    fn from(why: FromUtf8Error) -> Self {
+        Error::Utf8(why)
+    }
+}
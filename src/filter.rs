@@ -0,0 +1,398 @@
+// Implementation of scanline filtering from the PNG spec[http://www.libpng.org/pub/png/spec/1.2/PNG-Filters.html]
+use crate::error::{Error, Result};
+use crate::ihdr::{Ihdr, InterlaceMethod};
+
+/// First column, first row, column step and row step of each Adam7 pass
+const ADAM7: [(usize, usize, usize, usize); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+/// One reduced image of an interlaced PNG, or the whole image when it isn't interlaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pass {
+    /// Column and row of the pass's first pixel in the full image
+    pub origin: (usize, usize),
+    /// Distance between the pass's pixels in the full image
+    pub step: (usize, usize),
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Image data with its filters undone, rows stored back to back without filter bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scanlines {
+    pub passes: Vec<Pass>,
+    /// The filter each row was stored with, in storage order
+    pub filters: Vec<FilterType>,
+    pub data: Vec<u8>,
+    /// Bytes per complete pixel, rounded up to one as the filters expect
+    bytes_per_pixel: usize,
+    bits_per_pixel: usize,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<FilterType> {
+        FilterType::ALL.get(usize::from(byte)).copied()
+    }
+
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            FilterType::None => 0,
+            FilterType::Sub => 1,
+            FilterType::Up => 2,
+            FilterType::Average => 3,
+            FilterType::Paeth => 4,
+        }
+    }
+}
+
+impl Pass {
+    /// Bytes in one of the pass's rows, not counting the filter byte
+    pub fn row_length(&self, bits_per_pixel: usize) -> usize {
+        (self.width * bits_per_pixel).div_ceil(8)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+}
+
+/// The passes `ihdr`'s image is stored in, leaving out empty ones
+pub fn passes(ihdr: &Ihdr) -> Vec<Pass> {
+    let (width, height) = (ihdr.width as usize, ihdr.height as usize);
+    match ihdr.interlace_method {
+        InterlaceMethod::None => vec![Pass {
+            origin: (0, 0),
+            step: (1, 1),
+            width,
+            height,
+        }],
+        InterlaceMethod::Adam7 => ADAM7
+            .iter()
+            .map(|&(x, y, dx, dy)| Pass {
+                origin: (x, y),
+                step: (dx, dy),
+                width: width.saturating_sub(x).div_ceil(dx),
+                height: height.saturating_sub(y).div_ceil(dy),
+            })
+            .filter(|pass| !pass.is_empty())
+            .collect(),
+    }
+}
+
+impl Scanlines {
+    /// Builds unfiltered scanlines, recording `filter` as the filter for every row
+    pub fn new(ihdr: &Ihdr, data: Vec<u8>, filter: FilterType) -> Result<Scanlines> {
+        let passes = passes(ihdr);
+        let bits_per_pixel = ihdr.bits_per_pixel();
+        let expected: usize = passes
+            .iter()
+            .map(|pass| pass.row_length(bits_per_pixel) * pass.height)
+            .sum();
+        if data.len() != expected {
+            return Err(invalid(format!(
+                "expected {} bytes of scanlines, found {}",
+                expected,
+                data.len()
+            )));
+        }
+
+        let rows = passes.iter().map(|pass| pass.height).sum();
+        Ok(Scanlines {
+            passes,
+            filters: vec![filter; rows],
+            data,
+            bytes_per_pixel: bits_per_pixel.div_ceil(8),
+            bits_per_pixel,
+        })
+    }
+
+    /// Undoes the filters of `filtered`, the inflated contents of the IDAT chunks
+    pub fn unfilter(ihdr: &Ihdr, filtered: &[u8]) -> Result<Scanlines> {
+        let passes = passes(ihdr);
+        let bits_per_pixel = ihdr.bits_per_pixel();
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+
+        let mut filters = Vec::new();
+        let mut data = Vec::with_capacity(filtered.len());
+        let mut remaining = filtered;
+        for pass in &passes {
+            let row_length = pass.row_length(bits_per_pixel);
+            let start = data.len();
+            for row in 0..pass.height {
+                let (line, rest) = match remaining.split_first() {
+                    Some((&filter, rest)) if rest.len() >= row_length => {
+                        let filter = FilterType::from_byte(filter)
+                            .ok_or_else(|| invalid(format!("unknown filter type {}", filter)))?;
+                        filters.push(filter);
+                        rest.split_at(row_length)
+                    }
+                    _ => return Err(invalid(String::from("image data is truncated"))),
+                };
+                remaining = rest;
+
+                let row_start = data.len();
+                data.extend_from_slice(line);
+                let (previous, current) = data[start..].split_at_mut(row_start - start);
+                let previous = match row {
+                    0 => None,
+                    _ => Some(&previous[previous.len() - row_length..]),
+                };
+                unfilter_row(
+                    filters[filters.len() - 1],
+                    bytes_per_pixel,
+                    previous,
+                    current,
+                );
+            }
+        }
+        if !remaining.is_empty() {
+            return Err(invalid(format!(
+                "{} bytes of image data left over",
+                remaining.len()
+            )));
+        }
+
+        Ok(Scanlines {
+            passes,
+            filters,
+            data,
+            bytes_per_pixel,
+            bits_per_pixel,
+        })
+    }
+
+    /// Picks each row's filter with the spec's suggested heuristic: the one leaving the
+    /// smallest sum of absolute differences, taking bytes as signed
+    pub fn select_filters(&mut self) {
+        let mut candidate = Vec::new();
+        let mut offset = 0;
+        let mut row_index = 0;
+        for pass in &self.passes {
+            let row_length = pass.row_length(self.bits_per_pixel);
+            for row in 0..pass.height {
+                let current = &self.data[offset..offset + row_length];
+                let previous = match row {
+                    0 => None,
+                    _ => Some(&self.data[offset - row_length..offset]),
+                };
+                self.filters[row_index] = *FilterType::ALL
+                    .iter()
+                    .min_by_key(|&&filter| {
+                        candidate.clear();
+                        filter_row(
+                            filter,
+                            self.bytes_per_pixel,
+                            previous,
+                            current,
+                            &mut candidate,
+                        );
+                        candidate
+                            .iter()
+                            .map(|&byte| u64::from((byte as i8).unsigned_abs()))
+                            .sum::<u64>()
+                    })
+                    .unwrap_or(&FilterType::None);
+                offset += row_length;
+                row_index += 1;
+            }
+        }
+    }
+
+    /// Filters every row with its recorded filter, ready to be deflated into IDAT chunks
+    pub fn filter(&self) -> Vec<u8> {
+        let mut filtered = Vec::with_capacity(self.data.len() + self.filters.len());
+        let mut filters = self.filters.iter();
+        let mut offset = 0;
+        for pass in &self.passes {
+            let row_length = pass.row_length(self.bits_per_pixel);
+            for row in 0..pass.height {
+                let current = &self.data[offset..offset + row_length];
+                let previous = match row {
+                    0 => None,
+                    _ => Some(&self.data[offset - row_length..offset]),
+                };
+                let filter = *filters.next().unwrap_or(&FilterType::None);
+                filtered.push(filter.as_byte());
+                filter_row(
+                    filter,
+                    self.bytes_per_pixel,
+                    previous,
+                    current,
+                    &mut filtered,
+                );
+                offset += row_length;
+            }
+        }
+        filtered
+    }
+}
+
+// @notice: Reverses `filter` in place, `previous` is the unfiltered row above if any
+fn unfilter_row(filter: FilterType, bpp: usize, previous: Option<&[u8]>, row: &mut [u8]) {
+    let above = |i: usize| previous.map_or(0, |p| p[i]);
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { above(i - bpp) } else { 0 };
+        let predicted = predict(filter, left, above(i), upper_left);
+        row[i] = row[i].wrapping_add(predicted);
+    }
+}
+
+// @notice: Appends `row` filtered with `filter` to `out`, `previous` is the row above if any
+fn filter_row(
+    filter: FilterType,
+    bpp: usize,
+    previous: Option<&[u8]>,
+    row: &[u8],
+    out: &mut Vec<u8>,
+) {
+    let above = |i: usize| previous.map_or(0, |p| p[i]);
+    for i in 0..row.len() {
+        let left = if i >= bpp { row[i - bpp] } else { 0 };
+        let upper_left = if i >= bpp { above(i - bpp) } else { 0 };
+        out.push(row[i].wrapping_sub(predict(filter, left, above(i), upper_left)));
+    }
+}
+
+fn predict(filter: FilterType, left: u8, above: u8, upper_left: u8) -> u8 {
+    match filter {
+        FilterType::None => 0,
+        FilterType::Sub => left,
+        FilterType::Up => above,
+        FilterType::Average => ((u16::from(left) + u16::from(above)) / 2) as u8,
+        FilterType::Paeth => paeth(left, above, upper_left),
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let pa = (p - i16::from(a)).abs();
+    let pb = (p - i16::from(b)).abs();
+    let pc = (p - i16::from(c)).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidImageData(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn testing_scanlines(ihdr: &Ihdr) -> Scanlines {
+        let length: usize = passes(ihdr)
+            .iter()
+            .map(|pass| pass.row_length(ihdr.bits_per_pixel()) * pass.height)
+            .sum();
+        let data = (0..length).map(|i| (i * 37 % 251) as u8).collect();
+        let mut scanlines = Scanlines::new(ihdr, data, FilterType::None).unwrap();
+        for (row, filter) in scanlines.filters.iter_mut().enumerate() {
+            *filter = FilterType::ALL[row % 5];
+        }
+        scanlines
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let ihdr = Ihdr::new(7, 6, 8, ColorType::Rgb, InterlaceMethod::None).unwrap();
+        let scanlines = testing_scanlines(&ihdr);
+        let filtered = scanlines.filter();
+        assert_eq!(filtered.len(), 6 * (1 + 7 * 3));
+        assert_eq!(Scanlines::unfilter(&ihdr, &filtered).unwrap(), scanlines);
+    }
+
+    #[test]
+    fn test_interlaced_filter_round_trip() {
+        let ihdr = Ihdr::new(9, 5, 4, ColorType::Grayscale, InterlaceMethod::Adam7).unwrap();
+        let scanlines = testing_scanlines(&ihdr);
+        assert_eq!(
+            Scanlines::unfilter(&ihdr, &scanlines.filter()).unwrap(),
+            scanlines
+        );
+    }
+
+    #[test]
+    fn test_adam7_passes() {
+        let ihdr = Ihdr::new(3, 3, 8, ColorType::Grayscale, InterlaceMethod::Adam7).unwrap();
+        let sizes: Vec<(usize, usize)> = passes(&ihdr)
+            .iter()
+            .map(|pass| (pass.width, pass.height))
+            .collect();
+        // The passes starting at column 4 and row 4 are empty
+        assert_eq!(sizes, [(1, 1), (1, 1), (2, 1), (1, 2), (3, 1)]);
+    }
+
+    #[test]
+    fn test_select_filters() {
+        let ihdr = Ihdr::new(8, 3, 8, ColorType::Grayscale, InterlaceMethod::None).unwrap();
+        let data = [
+            [0, 10, 20, 30, 40, 50, 60, 70],
+            [0, 10, 20, 30, 40, 50, 60, 70],
+            [9, 9, 9, 9, 9, 9, 9, 9],
+        ]
+        .concat();
+        let mut scanlines = Scanlines::new(&ihdr, data, FilterType::None).unwrap();
+        scanlines.select_filters();
+        assert_eq!(
+            scanlines.filters,
+            [FilterType::Sub, FilterType::Up, FilterType::Sub]
+        );
+        assert_eq!(
+            Scanlines::unfilter(&ihdr, &scanlines.filter()).unwrap(),
+            scanlines
+        );
+    }
+
+    #[test]
+    fn test_known_filters() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Grayscale, InterlaceMethod::None).unwrap();
+        // Row 1 stored with Sub, row 2 with Paeth
+        let filtered = [1, 10, 5, 4, 3, 1];
+        let scanlines = Scanlines::unfilter(&ihdr, &filtered).unwrap();
+        assert_eq!(scanlines.data, [10, 15, 13, 16]);
+        assert_eq!(scanlines.filters, [FilterType::Sub, FilterType::Paeth]);
+    }
+
+    #[test]
+    fn test_invalid_image_data() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Grayscale, InterlaceMethod::None).unwrap();
+        assert!(matches!(
+            Scanlines::unfilter(&ihdr, &[0, 1, 2, 5, 1, 2]),
+            Err(Error::InvalidImageData(_))
+        ));
+        assert!(Scanlines::unfilter(&ihdr, &[0, 1, 2, 0, 1]).is_err());
+        assert!(Scanlines::unfilter(&ihdr, &[0, 1, 2, 0, 1, 2, 0]).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::OsRng;
+
+use crate::error::{Error, Result};
+
+/// Marks chunk data as one fragment of a larger payload
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'F'];
+const VERSION: u8 = 1;
+/// Magic, version, payload id, sequence number and total count
+pub const HEADER_LENGTH: usize = MAGIC.len() + 1 + 4 + 4 + 4;
+
+/// Most missing sequence numbers listed in `Error::BrokenFragments`
+const MAXIMUM_LISTED_MISSING: usize = 16;
+
+/// Largest chunk data `encode` writes unless told otherwise
+pub const DEFAULT_MAXIMUM_SIZE: usize = 1 << 20;
+
+/// One piece of a payload too large for a single chunk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fragment {
+    /// Shared by every fragment of the same payload
+    pub payload_id: u32,
+    /// Position of this fragment, counting from zero
+    pub sequence: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.data.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&self.payload_id.to_be_bytes());
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Fragment {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        if !is_fragment(bytes) {
+            return Err(invalid(String::from("missing fragment magic bytes")));
+        }
+        if bytes.len() < HEADER_LENGTH {
+            return Err(invalid(String::from("fragment header is truncated")));
+        }
+        if bytes[MAGIC.len()] != VERSION {
+            return Err(invalid(format!(
+                "unsupported fragment version {}",
+                bytes[MAGIC.len()]
+            )));
+        }
+
+        let field = |index: usize| {
+            let start = MAGIC.len() + 1 + index * 4;
+            u32::from_be_bytes([
+                bytes[start],
+                bytes[start + 1],
+                bytes[start + 2],
+                bytes[start + 3],
+            ])
+        };
+        let fragment = Fragment {
+            payload_id: field(0),
+            sequence: field(1),
+            total: field(2),
+            data: bytes[HEADER_LENGTH..].to_vec(),
+        };
+        if fragment.sequence >= fragment.total {
+            return Err(invalid(format!(
+                "fragment {} of a payload with {} fragments",
+                fragment.sequence, fragment.total
+            )));
+        }
+        Ok(fragment)
+    }
+}
+
+/// Whether `data` starts with a fragment header
+pub fn is_fragment(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+/// Splits `data` into chunk data no longer than `maximum_size` bytes each, headers
+/// included. Data that already fits is returned as it is.
+pub fn split(data: &[u8], maximum_size: usize) -> Result<Vec<Vec<u8>>> {
+    if data.len() <= maximum_size && !is_fragment(data) {
+        return Ok(vec![data.to_vec()]);
+    }
+    if maximum_size <= HEADER_LENGTH {
+        return Err(invalid(format!(
+            "maximum chunk size must be over {} bytes to fit a fragment",
+            HEADER_LENGTH
+        )));
+    }
+
+    let pieces = data.chunks(maximum_size - HEADER_LENGTH);
+    let total = u32::try_from(pieces.len())
+        .map_err(|_| invalid(String::from("payload needs too many fragments")))?;
+    let payload_id = OsRng.next_u32();
+    Ok(pieces
+        .enumerate()
+        .map(|(sequence, piece)| {
+            Fragment {
+                payload_id,
+                sequence: sequence as u32,
+                total,
+                data: piece.to_vec(),
+            }
+            .to_bytes()
+        })
+        .collect())
+}
+
+/// Puts the payload the first fragment belongs to back together, ignoring fragments of
+/// other payloads. Fails with `Error::BrokenFragments` if any are missing or repeated.
+pub fn reassemble(fragments: &[Fragment]) -> Result<Vec<u8>> {
+    let first = fragments
+        .first()
+        .ok_or_else(|| invalid(String::from("no fragments to reassemble")))?;
+
+    let mut pieces: BTreeMap<u32, &[u8]> = BTreeMap::new();
+    let mut duplicated = Vec::new();
+    for fragment in fragments
+        .iter()
+        .filter(|f| f.payload_id == first.payload_id)
+    {
+        if fragment.total != first.total {
+            return Err(invalid(format!(
+                "fragments of payload {:08x} disagree on their count",
+                first.payload_id
+            )));
+        }
+        if pieces.insert(fragment.sequence, &fragment.data).is_some()
+            && !duplicated.contains(&fragment.sequence)
+        {
+            duplicated.push(fragment.sequence);
+        }
+    }
+
+    // `total` comes from the file, so never walk it: the filter skips at most one
+    // sequence per fragment present before `take` stops it
+    let missing_count = first.total - pieces.len() as u32;
+    let missing: Vec<u32> = (0..first.total)
+        .filter(|sequence| !pieces.contains_key(sequence))
+        .take(MAXIMUM_LISTED_MISSING)
+        .collect();
+    if missing_count > 0 || !duplicated.is_empty() {
+        duplicated.sort_unstable();
+        return Err(Error::BrokenFragments {
+            payload_id: first.payload_id,
+            missing,
+            missing_count,
+            duplicated,
+        });
+    }
+
+    Ok(pieces.into_values().flatten().copied().collect())
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidPayload(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragments_of(data: &[u8], maximum_size: usize) -> Vec<Fragment> {
+        split(data, maximum_size)
+            .unwrap()
+            .iter()
+            .map(|bytes| Fragment::try_from(bytes.as_slice()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_small_data_isnt_split() {
+        assert_eq!(split(b"Hello", 100).unwrap(), vec![b"Hello".to_vec()]);
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let data: Vec<u8> = (0..100).collect();
+        let pieces = split(&data, HEADER_LENGTH + 30).unwrap();
+        assert_eq!(pieces.len(), 4);
+        assert!(pieces.iter().all(|p| p.len() <= HEADER_LENGTH + 30));
+
+        let mut fragments: Vec<Fragment> = pieces
+            .iter()
+            .map(|bytes| Fragment::try_from(bytes.as_slice()).unwrap())
+            .collect();
+        fragments.reverse();
+        fragments.push(Fragment {
+            payload_id: fragments[0].payload_id.wrapping_add(1),
+            sequence: 0,
+            total: 1,
+            data: b"someone else's".to_vec(),
+        });
+        assert_eq!(reassemble(&fragments).unwrap(), data);
+    }
+
+    #[test]
+    fn test_missing_and_duplicated_fragments() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut fragments = fragments_of(&data, HEADER_LENGTH + 10);
+        fragments.remove(7);
+        fragments.remove(2);
+        fragments.push(fragments[4].clone());
+
+        match reassemble(&fragments) {
+            Err(Error::BrokenFragments {
+                missing,
+                duplicated,
+                ..
+            }) => {
+                assert_eq!(missing, [2, 7]);
+                assert_eq!(duplicated, [5]);
+            }
+            other => panic!("expected broken fragments, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_huge_total() {
+        let fragment = Fragment {
+            payload_id: 1,
+            sequence: 0,
+            total: u32::MAX,
+            data: vec![1, 2, 3],
+        };
+        let error = reassemble(&[fragment]).unwrap_err();
+        match &error {
+            Error::BrokenFragments {
+                missing,
+                missing_count,
+                ..
+            } => {
+                assert_eq!(missing.len(), MAXIMUM_LISTED_MISSING);
+                assert_eq!(missing[0], 1);
+                assert_eq!(*missing_count, u32::MAX - 1);
+            }
+            other => panic!("expected broken fragments, got {:?}", other),
+        }
+        assert!(error.to_string().ends_with(" and 4294967278 more"));
+    }
+
+    #[test]
+    fn test_invalid_fragments() {
+        assert!(split(&[0; 100], HEADER_LENGTH).is_err());
+
+        let mut bytes = Fragment {
+            payload_id: 1,
+            sequence: 3,
+            total: 3,
+            data: Vec::new(),
+        }
+        .to_bytes();
+        assert!(Fragment::try_from(bytes.as_slice()).is_err());
+        bytes.truncate(HEADER_LENGTH - 1);
+        assert!(Fragment::try_from(bytes.as_slice()).is_err());
+    }
+}
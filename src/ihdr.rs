@@ -0,0 +1,249 @@
+// Implementation of the IHDR section of PNG spec[http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.IHDR]
+use std::fmt::{self, Display};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+
+/// Largest width or height the spec allows
+const MAXIMUM_DIMENSION: u32 = (1 << 31) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn from_byte(byte: u8) -> Option<ColorType> {
+        match byte {
+            0 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::Rgb),
+            3 => Some(ColorType::Indexed),
+            4 => Some(ColorType::GrayscaleAlpha),
+            6 => Some(ColorType::Rgba),
+            _ => None,
+        }
+    }
+
+    pub fn as_byte(&self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    /// Number of samples making up one pixel
+    pub fn channels(&self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths the spec allows for this colour type
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale+alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterlaceMethod {
+    None,
+    Adam7,
+}
+
+/// Decoded contents of an IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ihdr {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlace_method: InterlaceMethod,
+}
+
+impl Ihdr {
+    pub const CHUNK_TYPE: &'static str = "IHDR";
+    const LENGTH: usize = 13;
+
+    /// Builds a header, checking it against the spec's rules
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+        interlace_method: InterlaceMethod,
+    ) -> Result<Ihdr> {
+        if width == 0 || width > MAXIMUM_DIMENSION || height == 0 || height > MAXIMUM_DIMENSION {
+            return Err(invalid(format!("bad dimensions {}x{}", width, height)));
+        }
+        if !color_type.allowed_bit_depths().contains(&bit_depth) {
+            return Err(invalid(format!(
+                "bit depth {} isn't allowed for {} images",
+                bit_depth, color_type
+            )));
+        }
+
+        Ok(Ihdr {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlace_method,
+        })
+    }
+
+    /// Number of bits used by one pixel
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * usize::from(self.bit_depth)
+    }
+
+    pub fn to_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Ihdr::LENGTH);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type.as_byte());
+        data.push(0);
+        data.push(0);
+        data.push(match self.interlace_method {
+            InterlaceMethod::None => 0,
+            InterlaceMethod::Adam7 => 1,
+        });
+
+        Chunk::new(ChunkType::new(b"IHDR"), data)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        if chunk.chunk_type().to_string() != Ihdr::CHUNK_TYPE {
+            return Err(invalid(format!(
+                "expected IHDR, found {}",
+                chunk.chunk_type()
+            )));
+        }
+
+        let data = chunk.data();
+        if data.len() != Ihdr::LENGTH {
+            return Err(invalid(format!(
+                "length is {}, expected {}",
+                data.len(),
+                Ihdr::LENGTH
+            )));
+        }
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let bit_depth = data[8];
+        let color_type = ColorType::from_byte(data[9])
+            .ok_or_else(|| invalid(format!("unknown colour type {}", data[9])))?;
+        if data[10] != 0 {
+            return Err(invalid(format!("unknown compression method {}", data[10])));
+        }
+        if data[11] != 0 {
+            return Err(invalid(format!("unknown filter method {}", data[11])));
+        }
+        let interlace_method = match data[12] {
+            0 => InterlaceMethod::None,
+            1 => InterlaceMethod::Adam7,
+            other => return Err(invalid(format!("unknown interlace method {}", other))),
+        };
+
+        Ihdr::new(width, height, bit_depth, color_type, interlace_method)
+    }
+}
+
+impl Display for Ihdr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}, {}-bit {}",
+            self.width, self.height, self.bit_depth, self.color_type
+        )?;
+        if self.interlace_method == InterlaceMethod::Adam7 {
+            write!(f, ", interlaced")?;
+        }
+        Ok(())
+    }
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidIhdr(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn ihdr_chunk(data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_ihdr_from_chunk() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+        let ihdr = Ihdr::try_from(&chunk).unwrap();
+        assert_eq!(ihdr.width, 50);
+        assert_eq!(ihdr.height, 40);
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, ColorType::Rgba);
+        assert_eq!(ihdr.interlace_method, InterlaceMethod::None);
+        assert_eq!(ihdr.bits_per_pixel(), 32);
+    }
+
+    #[test]
+    fn test_ihdr_round_trip() {
+        let ihdr = Ihdr::new(3, 7, 4, ColorType::Indexed, InterlaceMethod::Adam7).unwrap();
+        assert_eq!(Ihdr::try_from(&ihdr.to_chunk()).unwrap(), ihdr);
+    }
+
+    #[test]
+    fn test_invalid_bit_depth_for_color_type() {
+        let chunk = ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0]);
+        assert!(matches!(Ihdr::try_from(&chunk), Err(Error::InvalidIhdr(_))));
+        assert!(Ihdr::new(1, 1, 16, ColorType::Indexed, InterlaceMethod::None).is_err());
+        assert!(Ihdr::new(1, 1, 1, ColorType::Grayscale, InterlaceMethod::None).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_ihdr_fields() {
+        // Zero width
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 0, 0, 0, 0, 1, 8, 2, 0, 0, 0])).is_err());
+        // Unknown colour type
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 5, 0, 0, 0])).is_err());
+        // Unknown interlace method
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 2])).is_err());
+        // Wrong length
+        assert!(Ihdr::try_from(&ihdr_chunk(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0])).is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::error::{Error, Result};
+use crate::filter::{passes, FilterType, Scanlines};
+use crate::ihdr::{ColorType, Ihdr, InterlaceMethod};
+use crate::zlib::InflateLimits;
+
+/// Decoded pixels of a PNG, in rows from the top left.
+///
+/// Every sample takes one byte, or two big-endian bytes when `bit_depth` is 16. Samples
+/// keep their stored values: a 2-bit grey is 0 to 3 and an indexed pixel is its palette
+/// index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub color_type: ColorType,
+    pub bit_depth: u8,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    /// Builds an image, checking `pixels` holds exactly one sample per channel and pixel
+    pub fn new(
+        width: u32,
+        height: u32,
+        color_type: ColorType,
+        bit_depth: u8,
+        pixels: Vec<u8>,
+    ) -> Result<Image> {
+        let image = Image {
+            width,
+            height,
+            color_type,
+            bit_depth,
+            pixels,
+        };
+        image.header(InterlaceMethod::None)?;
+
+        let expected = width as usize * height as usize * image.bytes_per_pixel();
+        if image.pixels.len() != expected {
+            return Err(invalid(format!(
+                "expected {} bytes of pixels, found {}",
+                expected,
+                image.pixels.len()
+            )));
+        }
+        Ok(image)
+    }
+
+    pub fn bytes_per_sample(&self) -> usize {
+        match self.bit_depth {
+            16 => 2,
+            _ => 1,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bytes_per_sample() * self.color_type.channels()
+    }
+
+    /// The value of one channel of the pixel at column `x` and row `y`
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> u16 {
+        let pixel = (y as usize * self.width as usize + x as usize) * self.bytes_per_pixel();
+        let start = pixel + channel * self.bytes_per_sample();
+        match self.bit_depth {
+            16 => u16::from_be_bytes([self.pixels[start], self.pixels[start + 1]]),
+            _ => u16::from(self.pixels[start]),
+        }
+    }
+
+    /// The IHDR describing this image when stored with `interlace_method`
+    pub fn header(&self, interlace_method: InterlaceMethod) -> Result<Ihdr> {
+        Ihdr::new(
+            self.width,
+            self.height,
+            self.bit_depth,
+            self.color_type,
+            interlace_method,
+        )
+    }
+
+    /// Unpacks and deinterlaces unfiltered scanlines. Sub-byte samples take a byte each
+    /// once unpacked, so the pixels must fit in `limits.max_size` too.
+    pub fn from_scanlines(
+        ihdr: &Ihdr,
+        scanlines: &Scanlines,
+        limits: InflateLimits,
+    ) -> Result<Image> {
+        let mut image = Image {
+            width: ihdr.width,
+            height: ihdr.height,
+            color_type: ihdr.color_type,
+            bit_depth: ihdr.bit_depth,
+            pixels: Vec::new(),
+        };
+        let bytes_per_pixel = image.bytes_per_pixel();
+        let size =
+            (u64::from(ihdr.width) * u64::from(ihdr.height)).saturating_mul(bytes_per_pixel as u64);
+        if size > limits.max_size {
+            return Err(Error::LimitExceeded {
+                limit: "image size",
+                maximum: limits.max_size,
+            });
+        }
+        image.pixels = vec![0; size as usize];
+
+        let mut offset = 0;
+        for pass in passes(ihdr) {
+            let row_length = pass.row_length(ihdr.bits_per_pixel());
+            for row in 0..pass.height {
+                let line = &scanlines.data[offset..offset + row_length];
+                offset += row_length;
+
+                let y = pass.origin.1 + row * pass.step.1;
+                for column in 0..pass.width {
+                    let x = pass.origin.0 + column * pass.step.0;
+                    let target = (y * ihdr.width as usize + x) * bytes_per_pixel;
+                    let pixel = &mut image.pixels[target..target + bytes_per_pixel];
+                    unpack_pixel(line, column, ihdr, pixel);
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    /// Interlaces and packs the pixels into scanlines, every row recorded as unfiltered
+    pub fn to_scanlines(&self, interlace_method: InterlaceMethod) -> Result<Scanlines> {
+        let ihdr = self.header(interlace_method)?;
+        let bytes_per_pixel = self.bytes_per_pixel();
+
+        let mut data = Vec::new();
+        for pass in passes(&ihdr) {
+            let row_length = pass.row_length(ihdr.bits_per_pixel());
+            for row in 0..pass.height {
+                let start = data.len();
+                data.resize(start + row_length, 0);
+                let y = pass.origin.1 + row * pass.step.1;
+                for column in 0..pass.width {
+                    let x = pass.origin.0 + column * pass.step.0;
+                    let source = (y * self.width as usize + x) * bytes_per_pixel;
+                    let pixel = &self.pixels[source..source + bytes_per_pixel];
+                    pack_pixel(pixel, column, &ihdr, &mut data[start..]);
+                }
+            }
+        }
+        Scanlines::new(&ihdr, data, FilterType::None)
+    }
+}
+
+// @notice: Copies the `column`th pixel of a packed row into one byte per sample
+fn unpack_pixel(line: &[u8], column: usize, ihdr: &Ihdr, pixel: &mut [u8]) {
+    let bit_depth = usize::from(ihdr.bit_depth);
+    if bit_depth >= 8 {
+        let start = column * pixel.len();
+        pixel.copy_from_slice(&line[start..start + pixel.len()]);
+        return;
+    }
+
+    // Sub-byte depths only occur with a single channel
+    let bit = column * bit_depth;
+    let shift = 8 - bit_depth - bit % 8;
+    pixel[0] = (line[bit / 8] >> shift) & ((1 << bit_depth) - 1);
+}
+
+// @notice: The reverse of `unpack_pixel`, `line` must start zeroed
+fn pack_pixel(pixel: &[u8], column: usize, ihdr: &Ihdr, line: &mut [u8]) {
+    let bit_depth = usize::from(ihdr.bit_depth);
+    if bit_depth >= 8 {
+        let start = column * pixel.len();
+        line[start..start + pixel.len()].copy_from_slice(pixel);
+        return;
+    }
+
+    let bit = column * bit_depth;
+    let shift = 8 - bit_depth - bit % 8;
+    line[bit / 8] |= (pixel[0] & ((1 << bit_depth) - 1)) << shift;
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidImageData(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_image(color_type: ColorType, bit_depth: u8) -> Image {
+        let (width, height) = (11, 7);
+        let samples = width * height * color_type.channels();
+        let maximum = (1u32 << bit_depth) - 1;
+        let pixels = (0..samples as u32)
+            .flat_map(|i| {
+                let value = (i.wrapping_mul(2654435761) % (maximum + 1)) as u16;
+                match bit_depth {
+                    16 => value.to_be_bytes().to_vec(),
+                    _ => vec![value as u8],
+                }
+            })
+            .collect();
+        Image::new(width as u32, height as u32, color_type, bit_depth, pixels).unwrap()
+    }
+
+    #[test]
+    fn test_scanlines_round_trip() {
+        for color_type in [
+            ColorType::Grayscale,
+            ColorType::Rgb,
+            ColorType::Indexed,
+            ColorType::GrayscaleAlpha,
+            ColorType::Rgba,
+        ] {
+            for &bit_depth in color_type.allowed_bit_depths() {
+                let image = testing_image(color_type, bit_depth);
+                for interlace_method in [InterlaceMethod::None, InterlaceMethod::Adam7] {
+                    let ihdr = image.header(interlace_method).unwrap();
+                    let scanlines = image.to_scanlines(interlace_method).unwrap();
+                    let filtered = Scanlines::unfilter(&ihdr, &scanlines.filter()).unwrap();
+                    assert_eq!(
+                        Image::from_scanlines(&ihdr, &filtered, InflateLimits::default()).unwrap(),
+                        image
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_sub_byte_samples() {
+        let ihdr = Ihdr::new(3, 2, 2, ColorType::Grayscale, InterlaceMethod::None).unwrap();
+        let scanlines = Scanlines::new(&ihdr, vec![0b1101_1000, 0b0001_1100], FilterType::None);
+        let scanlines = scanlines.unwrap();
+        let image = Image::from_scanlines(&ihdr, &scanlines, InflateLimits::default()).unwrap();
+        assert_eq!(image.pixels, [3, 1, 2, 0, 1, 3]);
+        assert_eq!(image.sample(1, 1, 0), 1);
+
+        // Two bytes of scanlines unpack to six bytes of pixels
+        let limits = InflateLimits {
+            max_ratio: 1,
+            max_size: 5,
+        };
+        assert!(matches!(
+            Image::from_scanlines(&ihdr, &scanlines, limits),
+            Err(Error::LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_deinterlace() {
+        let ihdr = Ihdr::new(3, 3, 8, ColorType::Grayscale, InterlaceMethod::Adam7).unwrap();
+        // Passes 1, 4, 5, 6 and 7 hold 1, 1, 2, 2 and 3 pixels
+        let scanlines = Scanlines::new(&ihdr, (1..=9).collect(), FilterType::None).unwrap();
+        let image = Image::from_scanlines(&ihdr, &scanlines, InflateLimits::default()).unwrap();
+        assert_eq!(image.pixels, [1, 5, 2, 7, 8, 9, 3, 6, 4]);
+    }
+
+    #[test]
+    fn test_sixteen_bit_samples() {
+        let image = Image::new(1, 1, ColorType::Rgb, 16, vec![1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(image.sample(0, 0, 2), 0x0506);
+        assert!(Image::new(2, 1, ColorType::Rgb, 16, vec![0; 6]).is_err());
+        assert!(Image::new(1, 1, ColorType::Rgb, 4, vec![0; 3]).is_err());
+    }
+}
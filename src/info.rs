@@ -0,0 +1,183 @@
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::ihdr::Ihdr;
+use crate::png::{InflateLimits, Png};
+use crate::text::TextEntry;
+
+/// Longest text value shown in a summary before it is cut short
+const MAXIMUM_SUMMARY_TEXT: usize = 60;
+
+/// Everything the `info` command reports about one chunk
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ChunkInfo {
+    pub index: usize,
+    /// Where the chunk starts when the PNG is written out
+    pub offset: usize,
+    /// Where the chunk started in the file it was parsed from, left out for chunks added since
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_offset: Option<usize>,
+    pub chunk_type: String,
+    pub length: u32,
+    pub crc: u32,
+    pub critical: bool,
+    pub public: bool,
+    pub safe_to_copy: bool,
+    pub summary: Option<String>,
+}
+
+impl ChunkInfo {
+    /// Describes `chunk`, summarizing compressed text only as far as `limits` allow
+    pub fn new(index: usize, offset: usize, chunk: &Chunk, limits: InflateLimits) -> ChunkInfo {
+        let chunk_type = chunk.chunk_type();
+        ChunkInfo {
+            index,
+            offset,
+            source_offset: chunk.offset(),
+            chunk_type: chunk_type.to_string(),
+            length: chunk.length(),
+            crc: chunk.crc(),
+            critical: chunk_type.is_critical(),
+            public: chunk_type.is_public(),
+            safe_to_copy: chunk_type.is_safe_to_copy(),
+            summary: summarize(chunk, limits),
+        }
+    }
+
+    /// The chunk type's property bits spelled out
+    pub fn flags(&self) -> String {
+        [
+            if self.critical {
+                "critical"
+            } else {
+                "ancillary"
+            },
+            if self.public { "public" } else { "private" },
+            if self.safe_to_copy {
+                "safe-to-copy"
+            } else {
+                "unsafe-to-copy"
+            },
+        ]
+        .join(",")
+    }
+}
+
+impl Display for ChunkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4}  {:>10}  {}  {:>10}  {:#010x}  ",
+            self.index, self.offset, self.chunk_type, self.length, self.crc,
+        )?;
+        match &self.summary {
+            Some(summary) => write!(f, "{:<34}  {}", self.flags(), summary),
+            None => write!(f, "{}", self.flags()),
+        }
+    }
+}
+
+/// Describes every chunk of `png`, with offsets as they'd be in the serialized file
+pub fn chunk_infos(png: &Png) -> Vec<ChunkInfo> {
+    let mut offset = png.header().len();
+    png.chunks()
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let info = ChunkInfo::new(index, offset, chunk, png.inflate_limits());
+            offset += 12 + chunk.data().len();
+            info
+        })
+        .collect()
+}
+
+// @notice: Decodes a short description of the chunks we understand, never failing
+fn summarize(chunk: &Chunk, limits: InflateLimits) -> Option<String> {
+    match chunk.chunk_type().to_string().as_str() {
+        Ihdr::CHUNK_TYPE => Some(match Ihdr::try_from(chunk) {
+            Ok(ihdr) => ihdr.to_string(),
+            Err(why) => why.to_string(),
+        }),
+        "PLTE" => Some(format!("{} entries", chunk.data().len() / 3)),
+        _ if TextEntry::is_text_chunk(chunk) => {
+            Some(match TextEntry::from_chunk_limited(chunk, limits) {
+                Ok(entry) => format!("{}: {}", entry.keyword(), shorten(entry.text())),
+                Err(why) => why.to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+fn shorten(text: &str) -> String {
+    let escaped = text.escape_debug().to_string();
+    match escaped.char_indices().nth(MAXIMUM_SUMMARY_TEXT) {
+        Some((end, _)) => format!("{}...", &escaped[..end]),
+        None => escaped,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::{ColorType, InterlaceMethod};
+    use crate::png::InsertPosition;
+    use crate::text::Text;
+
+    fn testing_png() -> Png {
+        let ihdr = Ihdr::new(50, 40, 8, ColorType::Rgba, InterlaceMethod::None).unwrap();
+        Png::from_chunks(vec![
+            ihdr.to_chunk(),
+            Text::new("Title", &"dice\n".repeat(20)).to_chunk().unwrap(),
+            Chunk::new(ChunkType::new(b"IDAT"), vec![0xff; 100]),
+            Chunk::new(ChunkType::new(b"IEND"), Vec::new()),
+        ])
+    }
+
+    #[test]
+    fn test_chunk_infos() {
+        let infos = chunk_infos(&testing_png());
+        let offsets: Vec<usize> = infos.iter().map(|i| i.offset).collect();
+        assert_eq!(offsets, [8, 33, 151, 263]);
+
+        assert_eq!(infos[0].summary.as_deref(), Some("50x40, 8-bit RGBA"));
+        assert_eq!(infos[0].flags(), "critical,public,unsafe-to-copy");
+        assert_eq!(infos[1].flags(), "ancillary,public,safe-to-copy");
+        assert!(infos[1]
+            .summary
+            .as_ref()
+            .unwrap()
+            .starts_with("Title: dice\\n"));
+        assert!(infos[1].summary.as_ref().unwrap().ends_with("..."));
+        assert_eq!(infos[2].summary, None);
+    }
+
+    #[test]
+    fn test_source_offsets() {
+        let mut png = Png::try_from(testing_png().as_bytes().as_slice()).unwrap();
+        png.insert_chunk(
+            Text::new("Author", "Me").to_chunk().unwrap(),
+            InsertPosition::AfterIhdr,
+        );
+
+        let infos = chunk_infos(&png);
+        let offsets: Vec<usize> = infos.iter().map(|i| i.offset).collect();
+        assert_eq!(offsets, [8, 33, 54, 172, 284]);
+        let source_offsets: Vec<Option<usize>> = infos.iter().map(|i| i.source_offset).collect();
+        assert_eq!(
+            source_offsets,
+            [Some(8), None, Some(33), Some(151), Some(263)]
+        );
+    }
+
+    #[test]
+    fn test_chunk_info_display_binary_data() {
+        let bad_text = Chunk::new(ChunkType::new(b"tEXt"), vec![0xff, 0xfe]);
+        let info = ChunkInfo::new(0, 8, &bad_text, InflateLimits::default());
+        assert!(info.summary.is_some());
+        let _info_string = format!("{}", info);
+    }
+}
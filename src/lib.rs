@@ -1,5 +1,27 @@
+pub mod append;
 pub mod args;
 pub mod chunk;
 pub mod chunk_type;
 pub mod commands;
+pub mod crypto;
+pub mod encoder;
+pub mod error;
+pub mod filter;
+pub mod fragment;
+pub mod ihdr;
+pub mod image;
+pub mod info;
+pub mod lsb;
+pub mod message;
+pub mod output;
+pub mod payload;
 pub mod png;
+pub mod reader;
+pub mod repair;
+pub mod signature;
+pub mod text;
+pub mod validate;
+pub mod writer;
+mod zlib;
+
+pub use error::{Error, Result};
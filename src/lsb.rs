@@ -0,0 +1,202 @@
+// Hides data in the least significant bits of an image's colour samples, where it
+// survives tools that strip ancillary chunks
+use crate::error::{Error, Result};
+use crate::filter::Scanlines;
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+use crate::zlib::deflate;
+
+/// Marks the start of hidden data, so images without any aren't read as garbage
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'L'];
+/// Magic followed by the data's length as a u32
+const HEADER_LENGTH: usize = MAGIC.len() + 4;
+
+/// Bytes of data that can be hidden in `png`
+pub fn capacity(png: &Png) -> Result<usize> {
+    let ihdr = carrier_header(png)?;
+    let scanlines = Scanlines::unfilter(&ihdr, &png.inflate_image_data()?)?;
+    Ok((carriers(&ihdr, &scanlines).count() / 8).saturating_sub(HEADER_LENGTH))
+}
+
+/// Writes `data` into the low bits of `png`'s pixels, replacing anything hidden before
+pub fn embed(png: &mut Png, data: &[u8]) -> Result<()> {
+    let ihdr = carrier_header(png)?;
+    let mut scanlines = Scanlines::unfilter(&ihdr, &png.inflate_image_data()?)?;
+
+    let length = u32::try_from(data.len()).map_err(|_| Error::CapacityExceeded {
+        needed: data.len(),
+        available: u32::MAX as usize,
+    })?;
+    let mut framed = MAGIC.to_vec();
+    framed.extend_from_slice(&length.to_be_bytes());
+    framed.extend_from_slice(data);
+
+    let carriers: Vec<usize> = carriers(&ihdr, &scanlines).collect();
+    if framed.len() * 8 > carriers.len() {
+        return Err(Error::CapacityExceeded {
+            needed: data.len(),
+            available: (carriers.len() / 8).saturating_sub(HEADER_LENGTH),
+        });
+    }
+    for (bit, &offset) in bits(&framed).zip(carriers.iter()) {
+        scanlines.data[offset] = (scanlines.data[offset] & !1) | bit;
+    }
+
+    png.replace_image_data(deflate(&scanlines.filter())?);
+    Ok(())
+}
+
+/// Reads back data hidden by `embed`
+pub fn extract(png: &Png) -> Result<Vec<u8>> {
+    let ihdr = carrier_header(png)?;
+    let scanlines = Scanlines::unfilter(&ihdr, &png.inflate_image_data()?)?;
+    let mut bytes = carriers(&ihdr, &scanlines)
+        .map(|offset| scanlines.data[offset] & 1)
+        .collect::<Vec<u8>>()
+        .chunks_exact(8)
+        .map(|bits| bits.iter().fold(0, |byte, bit| (byte << 1) | bit))
+        .collect::<Vec<u8>>();
+
+    if bytes.len() < HEADER_LENGTH || !bytes.starts_with(&MAGIC) {
+        return Err(Error::InvalidPayload(String::from(
+            "no message is hidden in the image data",
+        )));
+    }
+    let length = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+    if bytes.len() - HEADER_LENGTH < length {
+        return Err(Error::InvalidPayload(format!(
+            "hidden data claims {} bytes but the image only holds {}",
+            length,
+            bytes.len() - HEADER_LENGTH
+        )));
+    }
+    bytes.truncate(HEADER_LENGTH + length);
+    Ok(bytes.split_off(HEADER_LENGTH))
+}
+
+// @notice: Decodes IHDR, checking its samples can carry data without visible damage
+fn carrier_header(png: &Png) -> Result<Ihdr> {
+    let ihdr = png.header_info()?;
+    if ihdr.color_type == ColorType::Indexed {
+        return Err(Error::UnsupportedImage(String::from(
+            "changing palette indices would change colours, not just their low bits",
+        )));
+    }
+    if ihdr.bit_depth < 8 {
+        return Err(Error::UnsupportedImage(format!(
+            "{}-bit samples are too coarse to hide data in",
+            ihdr.bit_depth
+        )));
+    }
+    Ok(ihdr)
+}
+
+// @notice: Offsets of the bytes holding the low bit of every colour sample, skipping alpha
+fn carriers<'a>(ihdr: &Ihdr, scanlines: &'a Scanlines) -> impl Iterator<Item = usize> + 'a {
+    let sample_length = usize::from(ihdr.bit_depth / 8);
+    let channels = ihdr.color_type.channels();
+    let colour_channels = match ihdr.color_type {
+        ColorType::GrayscaleAlpha | ColorType::Rgba => channels - 1,
+        _ => channels,
+    };
+    let pixel_length = sample_length * channels;
+
+    // Rows of 8 and 16-bit images have no padding, so pixels simply follow one another
+    (0..scanlines.data.len() / pixel_length).flat_map(move |pixel| {
+        (0..colour_channels)
+            .map(move |channel| pixel * pixel_length + (channel + 1) * sample_length - 1)
+    })
+}
+
+// @notice: Bits of `bytes`, most significant first
+fn bits(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::encoder::{encode, EncodeOptions, FilterStrategy};
+    use crate::filter::FilterType;
+    use crate::ihdr::InterlaceMethod;
+    use crate::png::InsertPosition;
+
+    fn testing_png(ihdr: Ihdr) -> Png {
+        let length = ihdr.width * ihdr.height * ihdr.color_type.channels() as u32;
+        let pixels: Vec<u8> = (0..length * u32::from(ihdr.bit_depth) / 8)
+            .map(|i| (i * 7 % 256) as u8)
+            .collect();
+        let options = EncodeOptions {
+            filter: FilterStrategy::Fixed(FilterType::Paeth),
+            palette: match ihdr.color_type {
+                ColorType::Indexed => Some(vec![[0; 3]; 256]),
+                _ => None,
+            },
+            ..EncodeOptions::default()
+        };
+        let mut png = encode(&ihdr, &pixels, &options).unwrap();
+        png.insert_chunk(
+            Chunk::new(ChunkType::new(b"tEXt"), b"Title\0dice".to_vec()),
+            InsertPosition::AfterIhdr,
+        );
+        png
+    }
+
+    #[test]
+    fn test_embed_and_extract() {
+        let ihdr = Ihdr::new(20, 10, 8, ColorType::Rgba, InterlaceMethod::None).unwrap();
+        let mut png = testing_png(ihdr);
+        assert_eq!(capacity(&png).unwrap(), 20 * 10 * 3 / 8 - HEADER_LENGTH);
+
+        embed(&mut png, b"Hidden in plain sight").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"Hidden in plain sight");
+        assert_eq!(png.chunks()[2].chunk_type().to_string(), "IDAT");
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_embed_only_touches_low_bits() {
+        let ihdr = Ihdr::new(16, 8, 16, ColorType::GrayscaleAlpha, InterlaceMethod::None).unwrap();
+        let mut png = testing_png(ihdr);
+        let before = Scanlines::unfilter(&ihdr, &png.inflate_image_data().unwrap()).unwrap();
+
+        embed(&mut png, &[0xff; 3]).unwrap();
+        let after = Scanlines::unfilter(&ihdr, &png.inflate_image_data().unwrap()).unwrap();
+        for (offset, (old, new)) in before.data.iter().zip(after.data.iter()).enumerate() {
+            match offset % 4 {
+                1 => assert_eq!(old & !1, new & !1),
+                _ => assert_eq!(old, new),
+            }
+        }
+        assert_eq!(extract(&png).unwrap(), [0xff; 3]);
+    }
+
+    #[test]
+    fn test_interlaced_embed() {
+        let ihdr = Ihdr::new(13, 11, 8, ColorType::Rgb, InterlaceMethod::Adam7).unwrap();
+        let mut png = testing_png(ihdr);
+        embed(&mut png, b"Adam7").unwrap();
+        assert_eq!(extract(&png).unwrap(), b"Adam7");
+    }
+
+    #[test]
+    fn test_lsb_errors() {
+        let ihdr = Ihdr::new(4, 4, 8, ColorType::Rgb, InterlaceMethod::None).unwrap();
+        let mut png = testing_png(ihdr);
+        assert!(matches!(
+            embed(&mut png, &[0; 100]),
+            Err(Error::CapacityExceeded { .. })
+        ));
+        assert!(extract(&png).is_err());
+
+        let ihdr = Ihdr::new(4, 4, 8, ColorType::Indexed, InterlaceMethod::None).unwrap();
+        assert!(matches!(
+            capacity(&testing_png(ihdr)),
+            Err(Error::UnsupportedImage(_))
+        ));
+    }
+}
@@ -1,11 +1,22 @@
+use std::process;
+
 use clap::StructOpt;
 use pico_pngme::args::Cli;
 use pico_pngme::commands::execute_command;
+use pico_pngme::output::{print_error, print_output};
 
 fn main() {
     let args = Cli::parse();
     match execute_command(args.command) {
-        Ok(()) => println!("Worked successfully."),
-        Err(why) => println!("{}", why),
+        Ok(output) => {
+            print_output(&output, args.format);
+            if !output.succeeded() {
+                process::exit(1);
+            }
+        }
+        Err(why) => {
+            print_error(&why, args.format);
+            process::exit(1);
+        }
     }
 }
@@ -0,0 +1,351 @@
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::chunk_type::ChunkType;
+use crate::crypto::{envelope_kind, EnvelopeKind};
+use crate::error::{Error, Result};
+use crate::fragment::{is_fragment, reassemble, Fragment};
+use crate::payload::{peek, read_field, write_field, FileInfo};
+use crate::png::Png;
+use crate::signature;
+
+/// Marks message data as carrying a name, kept outside any encryption so messages
+/// can be found without their key
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'N'];
+const VERSION: u8 = 1;
+
+/// One hidden message, which may be spread over several chunks
+#[derive(Debug)]
+pub struct Message {
+    pub chunk_type: ChunkType,
+    pub name: Option<String>,
+    /// Positions of the chunks holding the message, in file order
+    pub indices: Vec<usize>,
+    pub fragmented: bool,
+    /// The stored payload or envelope, or why it couldn't be read back
+    pub data: Result<Vec<u8>>,
+}
+
+/// Everything the `list` command reports about one message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MessageInfo {
+    /// Position of the message's first chunk
+    pub index: usize,
+    pub chunk_type: String,
+    pub name: Option<String>,
+    /// Bytes stored for the message, headers included
+    pub size: usize,
+    pub chunks: usize,
+    /// How the message is encrypted, if it is
+    pub encryption: Option<String>,
+    pub compressed: bool,
+    pub file: Option<FileInfo>,
+    /// Why the message can't be read back, if it can't
+    pub error: Option<String>,
+}
+
+impl Message {
+    /// Whether the message is called `name`, any message matches `None`
+    pub fn matches(&self, name: Option<&str>) -> bool {
+        name.is_none() || self.name.as_deref() == name
+    }
+}
+
+impl MessageInfo {
+    pub fn new(message: &Message, png: &Png) -> MessageInfo {
+        let size = message
+            .indices
+            .iter()
+            .map(|&index| png.chunks()[index].data().len())
+            .sum();
+
+        let mut info = MessageInfo {
+            index: message.indices[0],
+            chunk_type: message.chunk_type.to_string(),
+            name: message.name.clone(),
+            size,
+            chunks: message.indices.len(),
+            encryption: None,
+            compressed: false,
+            file: None,
+            error: None,
+        };
+        match &message.data {
+            Ok(data) => match envelope_kind(data) {
+                Some(EnvelopeKind::Passphrase) => {
+                    info.encryption = Some(String::from("passphrase"))
+                }
+                Some(EnvelopeKind::Recipients) => {
+                    info.encryption = Some(String::from("recipients"))
+                }
+                None => match peek(data) {
+                    Ok(payload) => {
+                        info.compressed = payload.compressed;
+                        info.file = payload.file;
+                    }
+                    Err(why) => info.error = Some(why.to_string()),
+                },
+            },
+            Err(why) => info.error = Some(why.to_string()),
+        }
+        info
+    }
+
+    /// The message's options spelled out
+    pub fn flags(&self) -> String {
+        let mut flags = Vec::new();
+        if let Some(encryption) = &self.encryption {
+            flags.push(format!("encrypted({})", encryption));
+        }
+        if self.compressed {
+            flags.push(String::from("compressed"));
+        }
+        if self.chunks > 1 {
+            flags.push(format!("fragments={}", self.chunks));
+        }
+        if let Some(file) = &self.file {
+            flags.push(format!("file={}", file.mime_type));
+        }
+        match flags.is_empty() {
+            true => String::from("-"),
+            false => flags.join(","),
+        }
+    }
+}
+
+impl Display for MessageInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:>4}  {}  {:<16}  {:>10}  {}",
+            self.index,
+            self.chunk_type,
+            self.name.as_deref().unwrap_or("-"),
+            self.size,
+            self.flags(),
+        )?;
+        if let Some(file) = &self.file {
+            if !file.name.is_empty() {
+                write!(f, "  {}", file.name)?;
+            }
+        }
+        if let Some(error) = &self.error {
+            write!(f, "  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether chunks of `chunk_type` can hold messages: private ancillary chunks other than
+/// the signature chunk
+pub fn is_message_type(chunk_type: &ChunkType) -> bool {
+    !chunk_type.is_critical()
+        && !chunk_type.is_public()
+        && chunk_type.to_string() != signature::CHUNK_TYPE
+}
+
+/// Puts `name` in front of `data`, unnamed messages are stored as they are
+pub fn label(name: Option<&str>, data: Vec<u8>) -> Result<Vec<u8>> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(data),
+    };
+    if name.is_empty() {
+        return Err(Error::InvalidPayload(String::from(
+            "message names can't be empty",
+        )));
+    }
+
+    let mut labelled = MAGIC.to_vec();
+    labelled.push(VERSION);
+    write_field(&mut labelled, name)?;
+    labelled.extend(data);
+    Ok(labelled)
+}
+
+/// Splits data made by `label` back into the name and the data
+pub fn unlabel(data: Vec<u8>) -> Result<(Option<String>, Vec<u8>)> {
+    let rest = match data.strip_prefix(&MAGIC) {
+        Some(rest) => rest,
+        None => return Ok((None, data)),
+    };
+    let mut rest = match rest.split_first() {
+        Some((&VERSION, rest)) => rest,
+        Some((version, _)) => {
+            return Err(Error::InvalidPayload(format!(
+                "unsupported name version {}",
+                version
+            )))
+        }
+        None => return Err(Error::InvalidPayload(String::from("name is truncated"))),
+    };
+    let name = read_field(&mut rest)?;
+    Ok((Some(name), rest.to_vec()))
+}
+
+/// Every message in `png` stored in chunks of `chunk_type`, or in any message chunk when
+/// it's `None`. Fragments are put back together in the order their first chunk appears.
+pub fn messages(png: &Png, chunk_type: Option<&ChunkType>) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    // Fragments for each fragmented message, by position in `messages`
+    let mut fragments: Vec<(usize, u32, Vec<Fragment>)> = Vec::new();
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let wanted = match chunk_type {
+            Some(chunk_type) => chunk.chunk_type() == chunk_type,
+            None => is_message_type(chunk.chunk_type()),
+        };
+        if !wanted {
+            continue;
+        }
+
+        if !is_fragment(chunk.data()) {
+            messages.push(Message {
+                chunk_type: chunk.chunk_type().clone(),
+                name: None,
+                indices: vec![index],
+                fragmented: false,
+                data: Ok(chunk.data().to_vec()),
+            });
+            continue;
+        }
+
+        let fragment = match Fragment::try_from(chunk.data()) {
+            Ok(fragment) => fragment,
+            Err(why) => {
+                messages.push(Message {
+                    chunk_type: chunk.chunk_type().clone(),
+                    name: None,
+                    indices: vec![index],
+                    fragmented: true,
+                    data: Err(why),
+                });
+                continue;
+            }
+        };
+        let group = fragments.iter_mut().find(|(position, payload_id, _)| {
+            *payload_id == fragment.payload_id
+                && messages[*position].chunk_type == *chunk.chunk_type()
+        });
+        match group {
+            Some((position, _, group)) => {
+                messages[*position].indices.push(index);
+                group.push(fragment);
+            }
+            None => {
+                fragments.push((messages.len(), fragment.payload_id, vec![fragment]));
+                messages.push(Message {
+                    chunk_type: chunk.chunk_type().clone(),
+                    name: None,
+                    indices: vec![index],
+                    fragmented: true,
+                    data: Ok(Vec::new()),
+                });
+            }
+        }
+    }
+
+    for (position, _, group) in fragments {
+        messages[position].data = reassemble(&group);
+    }
+    for message in messages.iter_mut() {
+        if let Ok(data) = &mut message.data {
+            match unlabel(std::mem::take(data)) {
+                Ok((name, data)) => {
+                    message.name = name;
+                    message.data = Ok(data);
+                }
+                Err(why) => message.data = Err(why),
+            }
+        }
+    }
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::fragment::{split, HEADER_LENGTH};
+    use crate::payload::Payload;
+
+    fn message_chunks(name: Option<&str>, data: &[u8], maximum_size: usize) -> Vec<Chunk> {
+        let labelled = label(name, data.to_vec()).unwrap();
+        split(&labelled, maximum_size)
+            .unwrap()
+            .into_iter()
+            .map(|piece| Chunk::new(ChunkType::new(b"ruSt"), piece))
+            .collect()
+    }
+
+    fn testing_png() -> Png {
+        let mut chunks = vec![Chunk::new(ChunkType::new(b"IHDR"), vec![0; 13])];
+        chunks.extend(message_chunks(None, b"plain", 100));
+        chunks.extend(message_chunks(Some("big"), &[7; 100], HEADER_LENGTH + 40));
+        chunks.push(Chunk::new(ChunkType::new(b"tEXt"), b"Title\0dice".to_vec()));
+        chunks.extend(message_chunks(Some("note"), b"hello", 100));
+        chunks.push(Chunk::new(ChunkType::new(b"IEND"), Vec::new()));
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_messages() {
+        let png = testing_png();
+        let messages = messages(&png, None);
+        assert_eq!(messages.len(), 3);
+
+        assert_eq!(messages[0].name, None);
+        assert_eq!(messages[0].data.as_ref().unwrap(), b"plain");
+
+        assert_eq!(messages[1].name.as_deref(), Some("big"));
+        assert!(messages[1].fragmented);
+        assert_eq!(messages[1].indices, [2, 3, 4]);
+        assert_eq!(messages[1].data.as_ref().unwrap(), &[7; 100]);
+
+        assert!(messages[2].matches(Some("note")));
+        assert!(messages[2].matches(None));
+        assert!(!messages[2].matches(Some("big")));
+        assert_eq!(messages[2].indices, [6]);
+    }
+
+    #[test]
+    fn test_message_info() {
+        let mut payload = Payload::from_file("dice.png", vec![1, 2, 3]);
+        payload.compressed = true;
+        let data = label(Some("picture"), payload.to_bytes().unwrap()).unwrap();
+        let png = Png::from_chunks(vec![Chunk::new(ChunkType::new(b"ruSt"), data)]);
+
+        let messages = messages(&png, Some(&ChunkType::new(b"ruSt")));
+        let info = MessageInfo::new(&messages[0], &png);
+        assert_eq!(info.name.as_deref(), Some("picture"));
+        assert!(info.compressed);
+        assert_eq!(info.flags(), "compressed,file=image/png");
+        assert_eq!(info.error, None);
+    }
+
+    #[test]
+    fn test_broken_message_is_listed() {
+        let mut png = testing_png();
+        let mut index = 0;
+        png.retain_chunks(|_| {
+            index += 1;
+            index != 4
+        });
+
+        let messages = messages(&png, None);
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(
+            messages[1].data,
+            Err(Error::BrokenFragments { .. })
+        ));
+        assert!(MessageInfo::new(&messages[1], &png).error.is_some());
+    }
+
+    #[test]
+    fn test_is_message_type() {
+        assert!(is_message_type(&ChunkType::new(b"ruSt")));
+        assert!(!is_message_type(&ChunkType::new(b"tEXt")));
+        assert!(!is_message_type(&ChunkType::new(b"siGN")));
+    }
+}
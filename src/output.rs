@@ -0,0 +1,415 @@
+use std::fmt::{self, Display};
+
+use colored::Colorize;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::args::OutputFormat;
+use crate::error::Error;
+use crate::info::ChunkInfo;
+use crate::message::MessageInfo;
+use crate::payload::FileInfo;
+use crate::repair::Fix;
+use crate::signature::{ChunkStatus, CoveredChunk};
+use crate::validate::Diagnostic;
+
+/// The result of a successful command, printed as text or JSON by `print_output`
+#[derive(Debug, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Output {
+    Decode {
+        file_path: String,
+        chunk_type: String,
+        /// Left out when the payload was written to `output_file`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output_file: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<FileInfo>,
+    },
+    Encode {
+        file_path: String,
+        output_file: String,
+        chunk_type: String,
+    },
+    Remove {
+        file_path: String,
+        chunk_type: String,
+        /// Number of messages removed
+        removed: usize,
+    },
+    List {
+        file_path: String,
+        messages: Vec<MessageInfo>,
+    },
+    Print {
+        file_path: String,
+        chunks: Vec<ChunkInfo>,
+        #[serde(skip)]
+        dump: String,
+    },
+    Info {
+        file_path: String,
+        chunks: Vec<ChunkInfo>,
+        /// Number of bytes after IEND that aren't chunks
+        trailing_data: usize,
+    },
+    Keygen {
+        identity_file: String,
+        public_file: String,
+        public_key: String,
+    },
+    Sign {
+        file_path: String,
+        signature_file: Option<String>,
+        covered_types: Vec<String>,
+        public_key: String,
+    },
+    Verify {
+        file_path: String,
+        valid: bool,
+        signature_valid: bool,
+        trusted: bool,
+        signer: String,
+        chunks: Vec<CoveredChunk>,
+    },
+    Repair {
+        file_path: String,
+        /// Left out when nothing needed fixing, so nothing was written
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output_file: Option<String>,
+        fixes: Vec<Fix>,
+    },
+    Check {
+        file_path: String,
+        errors: usize,
+        warnings: usize,
+        diagnostics: Vec<Diagnostic>,
+    },
+}
+
+impl Output {
+    /// Whether the command wrote data to stdout, so the result has to go to stderr
+    pub fn uses_stdout(&self) -> bool {
+        matches!(self, Output::Decode { output_file: Some(path), .. } if path == "-")
+    }
+
+    /// Whether the command achieved what it checks for, decides the exit status
+    pub fn succeeded(&self) -> bool {
+        match self {
+            Output::Verify { valid, trusted, .. } => *valid && *trusted,
+            Output::Check { errors, .. } => *errors == 0,
+            _ => true,
+        }
+    }
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Decode {
+                message: Some(message),
+                ..
+            } => write!(f, "{}", message),
+            Output::Decode {
+                output_file, file, ..
+            } => {
+                write!(
+                    f,
+                    "{} Wrote payload to '{}'",
+                    "SUCCESS:".bright_green().bold(),
+                    output_file.as_deref().unwrap_or_default().blue(),
+                )?;
+                match file {
+                    Some(file) if file.name.is_empty() => {
+                        write!(f, " ({}, {} bytes)", file.mime_type, file.size)
+                    }
+                    Some(file) => write!(
+                        f,
+                        " (was '{}', {}, {} bytes)",
+                        file.name, file.mime_type, file.size
+                    ),
+                    None => Ok(()),
+                }
+            }
+            Output::Encode { output_file, .. } => write!(
+                f,
+                "{} Wrote message to '{}'",
+                "SUCCESS:".bright_green().bold(),
+                output_file.blue(),
+            ),
+            Output::Remove {
+                file_path,
+                chunk_type,
+                removed,
+            } => write!(
+                f,
+                "{} Removed {} {} message{} from '{}'",
+                "SUCCESS:".bright_green().bold(),
+                removed,
+                chunk_type,
+                if *removed == 1 { "" } else { "s" },
+                file_path.blue(),
+            ),
+            Output::List { messages, .. } => {
+                write!(
+                    f,
+                    "{}",
+                    format!(
+                        "{:>4}  {}  {:<16}  {:>10}  {}",
+                        "#", "type", "name", "size", "flags"
+                    )
+                    .bold()
+                )?;
+                for message in messages {
+                    write!(f, "\n{}", message)?;
+                }
+                Ok(())
+            }
+            Output::Print { dump, .. } => write!(f, "{}", dump),
+            Output::Keygen {
+                identity_file,
+                public_file,
+                public_key,
+            } => write!(
+                f,
+                "{} Wrote secret key to '{}' and public key to '{}'\nPublic key: {}",
+                "SUCCESS:".bright_green().bold(),
+                identity_file.blue(),
+                public_file.blue(),
+                public_key,
+            ),
+            Output::Sign {
+                file_path,
+                signature_file,
+                covered_types,
+                ..
+            } => write!(
+                f,
+                "{} Signed {} chunks of '{}'{}",
+                "SUCCESS:".bright_green().bold(),
+                covered_types.join(", "),
+                file_path.blue(),
+                match signature_file {
+                    Some(signature_file) => format!(", signature written to '{}'", signature_file),
+                    None => String::new(),
+                },
+            ),
+            Output::Verify {
+                valid,
+                signature_valid,
+                trusted,
+                signer,
+                chunks,
+                ..
+            } => {
+                match (signature_valid, trusted) {
+                    (false, _) => write!(f, "{} Signature is forged", "FAILED:".red().bold())?,
+                    (true, false) => write!(
+                        f,
+                        "{} Signed by an untrusted key {}",
+                        "FAILED:".red().bold(),
+                        signer
+                    )?,
+                    (true, _) if !valid => write!(
+                        f,
+                        "{} Signed by {}, but covered chunks changed since",
+                        "FAILED:".red().bold(),
+                        signer
+                    )?,
+                    (true, _) => write!(f, "Signed by {}", signer)?,
+                }
+                for chunk in chunks {
+                    let index = chunk.index.map_or(String::from("-"), |i| i.to_string());
+                    let status = match chunk.status {
+                        ChunkStatus::Intact => "intact".green(),
+                        ChunkStatus::Altered => "altered".red(),
+                        ChunkStatus::Missing => "missing".red(),
+                        ChunkStatus::Added => "added".red(),
+                    };
+                    write!(f, "\n{:>4}  {}  {}", index, chunk.chunk_type, status)?;
+                }
+                Ok(())
+            }
+            Output::Repair {
+                file_path,
+                output_file,
+                fixes,
+            } => {
+                for fix in fixes {
+                    writeln!(f, "{}", fix)?;
+                }
+                match output_file {
+                    Some(output_file) => write!(
+                        f,
+                        "{} Applied {} fix{}, wrote '{}'",
+                        "SUCCESS:".bright_green().bold(),
+                        fixes.len(),
+                        if fixes.len() == 1 { "" } else { "es" },
+                        output_file.blue(),
+                    ),
+                    None => write!(
+                        f,
+                        "{} Nothing to repair in '{}'",
+                        "SUCCESS:".bright_green().bold(),
+                        file_path.blue(),
+                    ),
+                }
+            }
+            Output::Check {
+                file_path,
+                errors,
+                warnings,
+                diagnostics,
+            } => {
+                for diagnostic in diagnostics {
+                    writeln!(f, "{}", diagnostic)?;
+                }
+                match (errors, warnings) {
+                    (0, 0) => write!(
+                        f,
+                        "{} No problems found in '{}'",
+                        "SUCCESS:".bright_green().bold(),
+                        file_path.blue()
+                    ),
+                    (0, _) => write!(
+                        f,
+                        "{} '{}' is valid, with {} warning{}",
+                        "SUCCESS:".bright_green().bold(),
+                        file_path.blue(),
+                        warnings,
+                        if *warnings == 1 { "" } else { "s" },
+                    ),
+                    _ => write!(
+                        f,
+                        "{} {} error{} and {} warning{} in '{}'",
+                        "FAILED:".red().bold(),
+                        errors,
+                        if *errors == 1 { "" } else { "s" },
+                        warnings,
+                        if *warnings == 1 { "" } else { "s" },
+                        file_path.blue(),
+                    ),
+                }
+            }
+            Output::Info {
+                chunks,
+                trailing_data,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{}",
+                    format!(
+                        "{:>4}  {:>10}  {}  {:>10}  {:<10}  {:<34}  {}",
+                        "#", "offset", "type", "length", "crc", "flags", "summary"
+                    )
+                    .bold()
+                )?;
+                for info in chunks {
+                    write!(f, "\n{}", info)?;
+                }
+                if *trailing_data > 0 {
+                    write!(f, "\n{} bytes of trailing data after IEND", trailing_data)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// JSON document for a successful command
+pub fn output_document(output: &Output) -> Value {
+    let status = if output.succeeded() { "ok" } else { "failed" };
+    let mut document = json!({ "status": status });
+    if let (Some(document), Ok(Value::Object(fields))) =
+        (document.as_object_mut(), serde_json::to_value(output))
+    {
+        document.extend(fields);
+    }
+    document
+}
+
+/// JSON document for a failed command
+pub fn error_document(error: &Error) -> Value {
+    json!({
+        "status": "error",
+        "error": { "code": error.code(), "message": error.to_string() },
+    })
+}
+
+pub fn print_output(output: &Output, format: OutputFormat) {
+    let printed = match format {
+        OutputFormat::Text => output.to_string(),
+        OutputFormat::Json => output_document(output).to_string(),
+    };
+    match output.uses_stdout() {
+        true => eprintln!("{}", printed),
+        false => println!("{}", printed),
+    }
+}
+
+pub fn print_error(error: &Error, format: OutputFormat) {
+    match format {
+        OutputFormat::Text => eprintln!("{} {}", "Error:".red().bold(), error),
+        OutputFormat::Json => println!("{}", error_document(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_document() {
+        let output = Output::Decode {
+            file_path: String::from("dice.png"),
+            chunk_type: String::from("ruSt"),
+            message: Some(String::from("Hello")),
+            output_file: None,
+            file: None,
+        };
+        assert_eq!(
+            output_document(&output),
+            json!({
+                "status": "ok",
+                "command": "decode",
+                "file_path": "dice.png",
+                "chunk_type": "ruSt",
+                "message": "Hello",
+            })
+        );
+    }
+
+    #[test]
+    fn test_print_output_skips_dump() {
+        let output = Output::Print {
+            file_path: String::from("dice.png"),
+            chunks: Vec::new(),
+            dump: String::from("raw"),
+        };
+        assert!(output_document(&output).get("dump").is_none());
+    }
+
+    #[test]
+    fn test_failed_verify_document() {
+        let output = Output::Verify {
+            file_path: String::from("dice.png"),
+            valid: true,
+            signature_valid: true,
+            trusted: false,
+            signer: String::from("pngme-verifying-00"),
+            chunks: Vec::new(),
+        };
+        assert!(!output.succeeded());
+        assert_eq!(output_document(&output)["status"], "failed");
+    }
+
+    #[test]
+    fn test_error_document() {
+        let error = Error::ChunkNotFound(String::from("ruSt"));
+        assert_eq!(error_document(&error)["error"]["code"], "chunk_not_found");
+        assert_eq!(error_document(&error)["status"], "error");
+    }
+}
@@ -0,0 +1,347 @@
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+use crate::zlib::{deflate, inflate_limited, InflateLimits};
+
+/// Marks chunk data as a framed payload, like the encrypted envelope's magic 0x89 keeps
+/// plain UTF-8 messages from being mistaken for one
+const MAGIC: [u8; 4] = [0x89, b'P', b'M', b'P'];
+/// Every frame pngme stores starts with these bytes, whatever its kind
+const FRAME_PREFIX: [u8; 3] = [0x89, b'P', b'M'];
+const VERSION: u8 = 1;
+
+/// The body is zlib compressed
+const FLAG_COMPRESSED: u8 = 0b0000_0001;
+/// File metadata follows the flags
+const FLAG_FILE: u8 = 0b0000_0010;
+const KNOWN_FLAGS: u8 = FLAG_COMPRESSED | FLAG_FILE;
+
+/// MIME type used when the file's extension isn't one we know
+pub const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// A message as it's stored in a chunk, before any encryption
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Payload {
+    pub compressed: bool,
+    /// Set when the payload is an embedded file rather than a message
+    pub file: Option<FileInfo>,
+    pub data: Vec<u8>,
+}
+
+/// What's remembered about an embedded file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileInfo {
+    /// File name without any directories, empty when read from stdin
+    pub name: String,
+    pub mime_type: String,
+    /// Size of the original file in bytes
+    pub size: u64,
+}
+
+impl Payload {
+    pub fn new(data: Vec<u8>) -> Payload {
+        Payload {
+            compressed: false,
+            file: None,
+            data,
+        }
+    }
+
+    /// Wraps the contents of the file at `path`, `name` is stripped down to its file name
+    pub fn from_file(path: &str, data: Vec<u8>) -> Payload {
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Payload {
+            compressed: false,
+            file: Some(FileInfo {
+                mime_type: String::from(guess_mime_type(&name)),
+                name,
+                size: data.len() as u64,
+            }),
+            data,
+        }
+    }
+
+    /// Serializes the payload. Messages with no options are stored verbatim so they stay
+    /// readable by anything that reads the chunk as text.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let verbatim =
+            !self.compressed && self.file.is_none() && !self.data.starts_with(&FRAME_PREFIX);
+        if verbatim {
+            return Ok(self.data.clone());
+        }
+
+        let mut flags = 0;
+        if self.compressed {
+            flags |= FLAG_COMPRESSED;
+        }
+        if self.file.is_some() {
+            flags |= FLAG_FILE;
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(VERSION);
+        bytes.push(flags);
+        if let Some(file) = &self.file {
+            write_field(&mut bytes, &file.name)?;
+            write_field(&mut bytes, &file.mime_type)?;
+            bytes.extend_from_slice(&file.size.to_be_bytes());
+        }
+        match self.compressed {
+            true => bytes.extend(deflate(&self.data)?),
+            false => bytes.extend_from_slice(&self.data),
+        }
+        Ok(bytes)
+    }
+
+    /// Like `try_from`, but fails once a compressed body inflates past `limits`
+    pub fn from_bytes_limited(bytes: &[u8], limits: InflateLimits) -> Result<Self> {
+        let (info, body) = parse_header(bytes)?;
+        let data = match info.compressed {
+            true => inflate_limited(body, limits)?,
+            false => body.to_vec(),
+        };
+        if let Some(file) = &info.file {
+            if file.size != data.len() as u64 {
+                return Err(invalid(format!(
+                    "file should be {} bytes, found {}",
+                    file.size,
+                    data.len()
+                )));
+            }
+        }
+
+        Ok(Payload {
+            compressed: info.compressed,
+            file: info.file,
+            data,
+        })
+    }
+}
+
+impl TryFrom<&[u8]> for Payload {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Payload::from_bytes_limited(bytes, InflateLimits::default())
+    }
+}
+
+/// A payload's options, read without decoding its body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadInfo {
+    pub compressed: bool,
+    pub file: Option<FileInfo>,
+}
+
+/// Reads the options of the payload in `bytes`, leaving the body alone
+pub fn peek(bytes: &[u8]) -> Result<PayloadInfo> {
+    parse_header(bytes).map(|(info, _)| info)
+}
+
+// @notice: Splits a serialized payload into its options and its still encoded body
+fn parse_header(bytes: &[u8]) -> Result<(PayloadInfo, &[u8])> {
+    let rest = match bytes.strip_prefix(&MAGIC) {
+        Some(rest) => rest,
+        None => {
+            let info = PayloadInfo {
+                compressed: false,
+                file: None,
+            };
+            return Ok((info, bytes));
+        }
+    };
+
+    let (version, flags, mut body) = match rest {
+        [version, flags, body @ ..] => (*version, *flags, body),
+        _ => return Err(truncated()),
+    };
+    if version != VERSION {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+    if flags & !KNOWN_FLAGS != 0 {
+        return Err(invalid(format!("unknown flags {:#010b}", flags)));
+    }
+
+    let file = match flags & FLAG_FILE != 0 {
+        true => {
+            let name = read_field(&mut body)?;
+            let mime_type = read_field(&mut body)?;
+            let (size, rest) = body.split_first_chunk::<8>().ok_or_else(truncated)?;
+            body = rest;
+            Some(FileInfo {
+                name,
+                mime_type,
+                size: u64::from_be_bytes(*size),
+            })
+        }
+        false => None,
+    };
+
+    let info = PayloadInfo {
+        compressed: flags & FLAG_COMPRESSED != 0,
+        file,
+    };
+    Ok((info, body))
+}
+
+/// A MIME type for `name` going by its extension
+pub fn guess_mime_type(name: &str) -> &'static str {
+    let extension = match name.rsplit_once('.') {
+        Some((_, extension)) => extension.to_ascii_lowercase(),
+        None => return DEFAULT_MIME_TYPE,
+    };
+    match extension.as_str() {
+        "txt" | "md" => "text/plain",
+        "html" | "htm" => "text/html",
+        "csv" => "text/csv",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => DEFAULT_MIME_TYPE,
+    }
+}
+
+/// Whether `data` starts with a payload header
+pub fn is_framed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+// @notice: Writes a string prefixed by its length as a u16
+pub(crate) fn write_field(bytes: &mut Vec<u8>, field: &str) -> Result<()> {
+    let length = u16::try_from(field.len())
+        .map_err(|_| invalid(format!("{:?} is too long to store", field)))?;
+    bytes.extend_from_slice(&length.to_be_bytes());
+    bytes.extend_from_slice(field.as_bytes());
+    Ok(())
+}
+
+// @notice: Reads a field written by `write_field`, advancing `bytes` past it
+pub(crate) fn read_field(bytes: &mut &[u8]) -> Result<String> {
+    let (length, rest) = bytes.split_first_chunk::<2>().ok_or_else(truncated)?;
+    let length = usize::from(u16::from_be_bytes(*length));
+    if rest.len() < length {
+        return Err(truncated());
+    }
+    let (field, rest) = rest.split_at(length);
+    *bytes = rest;
+    Ok(String::from_utf8(field.to_vec())?)
+}
+
+fn truncated() -> Error {
+    invalid(String::from("header is truncated"))
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidPayload(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_payload_is_verbatim() {
+        let payload = Payload::new(b"Hello".to_vec());
+        let bytes = payload.to_bytes().unwrap();
+        assert_eq!(bytes, b"Hello");
+        assert!(!is_framed(&bytes));
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compressed_payload_round_trip() {
+        let mut payload = Payload::new("dice ".repeat(1000).into_bytes());
+        payload.compressed = true;
+        let bytes = payload.to_bytes().unwrap();
+        assert!(is_framed(&bytes));
+        assert!(bytes.len() < 100);
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_compressed_payload_bomb() {
+        let mut payload = Payload::new(vec![0; 1 << 20]);
+        payload.compressed = true;
+        let bytes = payload.to_bytes().unwrap();
+        let limits = InflateLimits {
+            max_size: 1 << 16,
+            ..InflateLimits::default()
+        };
+        assert!(matches!(
+            Payload::from_bytes_limited(&bytes, limits),
+            Err(Error::LimitExceeded {
+                limit: "inflated size",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_file_payload_round_trip() {
+        let data: Vec<u8> = (0..=255).collect();
+        let mut payload = Payload::from_file("../secrets/report.PDF", data);
+        assert_eq!(
+            payload.file,
+            Some(FileInfo {
+                name: String::from("report.PDF"),
+                mime_type: String::from("application/pdf"),
+                size: 256,
+            })
+        );
+        let bytes = payload.to_bytes().unwrap();
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+
+        payload.compressed = true;
+        let bytes = payload.to_bytes().unwrap();
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_data_that_looks_framed_is_framed() {
+        let payload = Payload::new(vec![0x89, b'P', b'M', b'P', 1, 0]);
+        let bytes = payload.to_bytes().unwrap();
+        assert_ne!(bytes, payload.data);
+        assert_eq!(Payload::try_from(bytes.as_slice()).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_file_size_mismatch() {
+        let mut bytes = Payload::from_file("a.txt", b"abc".to_vec())
+            .to_bytes()
+            .unwrap();
+        bytes.pop();
+        assert!(matches!(
+            Payload::try_from(bytes.as_slice()),
+            Err(Error::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_payload_headers() {
+        let truncated = [0x89, b'P', b'M', b'P', 1];
+        assert!(matches!(
+            Payload::try_from(truncated.as_slice()),
+            Err(Error::InvalidPayload(_))
+        ));
+
+        let unknown_flags = [0x89, b'P', b'M', b'P', 1, 0x80];
+        assert!(Payload::try_from(unknown_flags.as_slice()).is_err());
+
+        let bad_body = [0x89, b'P', b'M', b'P', 1, 1, 0xde, 0xad];
+        assert!(matches!(
+            Payload::try_from(bad_body.as_slice()),
+            Err(Error::Decompression(_))
+        ));
+    }
+}
@@ -1,37 +1,289 @@
-use std::error::Error;
 use std::fmt;
 use std::fmt::{Display, Formatter};
-use std::io::{BufReader, Read};
+use std::io::{Read, Write};
+
+use crate::chunk::{Chunk, MAXIMUM_LENGTH};
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::filter::Scanlines;
+use crate::ihdr::Ihdr;
+use crate::image::Image;
+use crate::reader::ChunkReader;
+use crate::repair::Damage;
+use crate::text::{is_latin1, InternationalText, Text, TextEntry};
+use crate::validate::{validate, Diagnostic};
+use crate::writer::ChunkWriter;
+use crate::zlib::{inflate_limited, MAX_DEFLATE_RATIO, MAX_INFLATED_SIZE};
+
+pub use crate::zlib::InflateLimits;
+
+/// Where `Png::insert_chunk` places a new chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertPosition {
+    /// Right before `IEND`, or at the end if there is none
+    BeforeIend,
+    /// Right after `IHDR`, or at the start if there is none
+    AfterIhdr,
+    /// Right before the first `IDAT`, or before `IEND` if there is none
+    BeforeFirstIdat,
+    /// At the given index, clamped to the number of chunks
+    At(usize),
+}
+
+/// How forgiving `Png::parse_with` is. The default accepts exactly what `Png::try_from` does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Keep ancillary chunks whose CRC doesn't match instead of failing, critical chunks
+    /// must always be intact
+    pub ignore_ancillary_crc_errors: bool,
+    /// Keep bytes after IEND as `Png::trailing_data` instead of failing on them
+    pub allow_trailing_data: bool,
+    /// Largest chunk data accepted, in bytes
+    pub max_chunk_size: u32,
+    /// Most chunks accepted
+    pub max_chunks: usize,
+    /// Most chunk data accepted across the whole file, in bytes
+    pub max_total_size: u64,
+    /// Most the image data, compressed text and message payloads may expand by when
+    /// inflated, checked when they're decoded
+    pub max_decompression_ratio: u32,
+    /// Most bytes any one of them may inflate to
+    pub max_inflated_size: u64,
+    /// Fail when the input ends without an IEND chunk
+    pub require_iend: bool,
+}
+
+impl ParseOptions {
+    /// Accepts whatever can be read, for ingesting files from the wild
+    pub fn lenient() -> ParseOptions {
+        ParseOptions {
+            ignore_ancillary_crc_errors: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    /// Rejects any damage, files cut off before IEND and anything after it
+    pub fn strict() -> ParseOptions {
+        ParseOptions {
+            allow_trailing_data: false,
+            require_iend: true,
+            ..ParseOptions::default()
+        }
+    }
+
+    /// Strict parsing with caps suited to untrusted uploads: chunks up to 16 MiB, 64 MiB
+    /// in total, at most 10,000 chunks and compressed data expanding at most 100 times, to
+    /// no more than 64 MiB
+    pub fn untrusted() -> ParseOptions {
+        ParseOptions {
+            max_chunk_size: 16 << 20,
+            max_chunks: 10_000,
+            max_total_size: 64 << 20,
+            max_decompression_ratio: 100,
+            max_inflated_size: 64 << 20,
+            ..ParseOptions::strict()
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        ParseOptions {
+            ignore_ancillary_crc_errors: false,
+            allow_trailing_data: true,
+            max_chunk_size: MAXIMUM_LENGTH,
+            max_chunks: usize::MAX,
+            max_total_size: u64::MAX,
+            max_decompression_ratio: MAX_DEFLATE_RATIO,
+            max_inflated_size: MAX_INFLATED_SIZE,
+            require_iend: false,
+        }
+    }
+}
 
-use crate::chunk::Chunk;
 pub struct Png {
     chunks: Vec<Chunk>,
+    /// Everything after IEND, such as an appended archive
+    trailing_data: Vec<u8>,
+    /// Carried over from `ParseOptions` for when compressed data is inflated
+    inflate_limits: InflateLimits,
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub(crate) const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png {
-        Png { chunks }
+        Png {
+            chunks,
+            trailing_data: Vec::new(),
+            inflate_limits: InflateLimits::default(),
+        }
+    }
+
+    /// Parses a whole PNG from a stream, one chunk at a time
+    pub fn from_reader<R: Read>(reader: R) -> Result<Png> {
+        Png::from_reader_with(reader, &ParseOptions::default())
+    }
+
+    /// Parses a whole PNG from `bytes`, as forgiving as `options` allow
+    pub fn parse_with(bytes: &[u8], options: &ParseOptions) -> Result<Png> {
+        Png::from_reader_with(bytes, options)
+    }
+
+    /// Parses a whole PNG from a stream, as forgiving as `options` allow
+    pub fn from_reader_with<R: Read>(reader: R, options: &ParseOptions) -> Result<Png> {
+        let mut reader = ChunkReader::new(reader)?;
+        let mut chunks = Vec::new();
+        let mut total_size = 0u64;
+        while let Some(chunk) = reader.read_chunk_with(options)? {
+            total_size += u64::from(chunk.length());
+            if total_size > options.max_total_size {
+                return Err(Error::LimitExceeded {
+                    limit: "total size",
+                    maximum: options.max_total_size,
+                });
+            }
+            chunks.push(chunk);
+        }
+
+        if options.require_iend && !reader.ended() {
+            return Err(Error::Truncated {
+                offset: reader.offset(),
+            });
+        }
+        let remaining = options.max_total_size - total_size;
+        let rest = reader.read_remaining(remaining)?;
+        if rest.len() as u64 > remaining {
+            return Err(Error::LimitExceeded {
+                limit: "total size",
+                maximum: options.max_total_size,
+            });
+        }
+        if !rest.is_empty() && !options.allow_trailing_data {
+            return Err(Error::TrailingData {
+                offset: reader.offset() - rest.len(),
+                length: rest.len(),
+            });
+        }
+
+        Ok(Png {
+            chunks,
+            trailing_data: rest,
+            inflate_limits: InflateLimits {
+                max_ratio: options.max_decompression_ratio,
+                max_size: options.max_inflated_size,
+            },
+        })
+    }
+
+    /// Parses as much of a damaged PNG as possible, returning the damage read past. Only
+    /// a bad signature or an I/O error fails.
+    pub fn from_reader_lenient<R: Read>(reader: R) -> Result<(Png, Vec<Damage>)> {
+        let mut reader = ChunkReader::new(reader)?;
+        let (chunks, damage) = reader.read_lenient()?;
+
+        let mut png = Png::from_chunks(chunks);
+        png.trailing_data = reader.read_remaining(u64::MAX)?;
+        Ok((png, damage))
     }
 
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk)
     }
 
-    pub fn remove_chunk(&mut self, _chunk_type: &str) -> Result<Chunk, ChunkNotFoundError> {
-        let chunk_type = String::from(_chunk_type);
-        if let Some(pos) = self
-            .chunks
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: InsertPosition) {
+        let index = self.insert_index(position);
+        self.chunks.insert(index, chunk)
+    }
+
+    /// Swaps the chunk at `index` for `chunk`, returning the old one
+    pub fn replace_chunk(&mut self, index: usize, chunk: Chunk) -> Chunk {
+        std::mem::replace(&mut self.chunks[index], chunk)
+    }
+
+    fn insert_index(&self, position: InsertPosition) -> usize {
+        match position {
+            InsertPosition::BeforeIend => self.position_of("IEND").unwrap_or(self.chunks.len()),
+            InsertPosition::AfterIhdr => self.position_of("IHDR").map_or(0, |pos| pos + 1),
+            InsertPosition::BeforeFirstIdat => self
+                .position_of("IDAT")
+                .unwrap_or_else(|| self.insert_index(InsertPosition::BeforeIend)),
+            InsertPosition::At(index) => index.min(self.chunks.len()),
+        }
+    }
+
+    fn position_of(&self, chunk_type: &str) -> Option<usize> {
+        self.chunks
             .iter()
             .position(|c| c.chunk_type().to_string() == chunk_type)
-        {
+    }
+
+    pub fn remove_chunk(&mut self, _chunk_type: &str) -> Result<Chunk> {
+        if let Some(pos) = self.position_of(_chunk_type) {
             Ok(self.chunks.remove(pos))
         } else {
-            Err(ChunkNotFoundError { chunk_type })
+            Err(Error::ChunkNotFound(String::from(_chunk_type)))
         }
     }
 
+    /// Keeps only the chunks `keep` returns true for, returning how many were removed
+    pub fn retain_chunks<F: FnMut(&Chunk) -> bool>(&mut self, keep: F) -> usize {
+        let before = self.chunks.len();
+        self.chunks.retain(keep);
+        before - self.chunks.len()
+    }
+
+    /// The compressed image data, joined from every IDAT chunk
+    pub fn image_data(&self) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|c| c.chunk_type().to_string() == "IDAT")
+            .flat_map(|c| c.data().iter().copied())
+            .collect()
+    }
+
+    /// Swaps the IDAT chunks for a single one holding `data`, where the first one was
+    pub fn replace_image_data(&mut self, data: Vec<u8>) {
+        let index = self.insert_index(InsertPosition::BeforeFirstIdat);
+        self.retain_chunks(|c| c.chunk_type().to_string() != "IDAT");
+        self.insert_chunk(
+            Chunk::new(ChunkType::new(b"IDAT"), data),
+            InsertPosition::At(index),
+        );
+    }
+
+    /// How far compressed data in the PNG may inflate, from the options it was parsed with
+    pub fn inflate_limits(&self) -> InflateLimits {
+        self.inflate_limits
+    }
+
+    /// Inflates the joined IDAT chunks, within the limits the PNG was parsed with
+    pub fn inflate_image_data(&self) -> Result<Vec<u8>> {
+        inflate_limited(&self.image_data(), self.inflate_limits)
+    }
+
+    /// Decodes the pixels: inflates the IDAT chunks, then undoes filtering and interlacing,
+    /// all within the limits the PNG was parsed with
+    pub fn decode_image(&self) -> Result<Image> {
+        let ihdr = self.header_info()?;
+        let scanlines = Scanlines::unfilter(&ihdr, &self.inflate_image_data()?)?;
+        Image::from_scanlines(&ihdr, &scanlines, self.inflate_limits)
+    }
+
+    /// Checks the spec's rules on which chunks appear, how often and in what order
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        validate(self)
+    }
+
+    /// Everything after IEND, kept as it is when the PNG is written
+    pub fn trailing_data(&self) -> &[u8] {
+        &self.trailing_data
+    }
+
+    pub fn set_trailing_data(&mut self, data: Vec<u8>) {
+        self.trailing_data = data
+    }
+
     pub fn header(&self) -> &[u8; 8] {
         &Png::STANDARD_HEADER
     }
@@ -40,6 +292,45 @@ impl Png {
         &self.chunks
     }
 
+    /// Decodes the IHDR chunk, which the spec requires to come first
+    pub fn header_info(&self) -> Result<Ihdr> {
+        match self.chunks.first() {
+            Some(chunk) if chunk.chunk_type().to_string() == Ihdr::CHUNK_TYPE => {
+                Ihdr::try_from(chunk)
+            }
+            _ => Err(Error::InvalidIhdr(String::from(
+                "IHDR must be the first chunk",
+            ))),
+        }
+    }
+
+    /// Decodes every tEXt, zTXt and iTXt chunk in file order
+    pub fn text_entries(&self) -> Result<Vec<TextEntry>> {
+        self.chunks
+            .iter()
+            .filter(|c| TextEntry::is_text_chunk(c))
+            .map(|c| TextEntry::from_chunk_limited(c, self.inflate_limits))
+            .collect()
+    }
+
+    /// Replaces every textual chunk for `keyword` with a single new one before IEND,
+    /// falling back to iTXt when `value` isn't Latin-1
+    pub fn set_text(&mut self, keyword: &str, value: &str) -> Result<()> {
+        let chunk = match is_latin1(value) {
+            true => Text::new(keyword, value).to_chunk()?,
+            false => InternationalText::new(keyword, value).to_chunk()?,
+        };
+
+        let limits = self.inflate_limits;
+        self.chunks.retain(|c| {
+            !(TextEntry::is_text_chunk(c)
+                && TextEntry::from_chunk_limited(c, limits)
+                    .is_ok_and(|entry| entry.keyword() == keyword))
+        });
+        self.insert_chunk(chunk, InsertPosition::BeforeIend);
+        Ok(())
+    }
+
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
         self.chunks
             .iter()
@@ -47,15 +338,28 @@ impl Png {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        [
-            &Png::STANDARD_HEADER,
-            self.chunks()
-                .iter()
-                .flat_map(|c| c.as_bytes())
-                .collect::<Vec<u8>>()
-                .as_slice(),
-        ]
-        .concat()
+        let size = self
+            .chunks()
+            .iter()
+            .map(|c| 12 + c.data().len())
+            .sum::<usize>();
+        let mut bytes =
+            Vec::with_capacity(Png::STANDARD_HEADER.len() + size + self.trailing_data.len());
+        bytes.extend_from_slice(&Png::STANDARD_HEADER);
+        for chunk in self.chunks() {
+            bytes.extend_from_slice(&chunk.as_bytes());
+        }
+        bytes.extend_from_slice(&self.trailing_data);
+        bytes
+    }
+
+    /// Serializes the signature and every chunk straight into `writer`
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let mut chunk_writer = ChunkWriter::new(writer)?;
+        for chunk in self.chunks() {
+            chunk_writer.write_chunk(chunk)?;
+        }
+        chunk_writer.write_raw(&self.trailing_data)
     }
 }
 
@@ -69,71 +373,11 @@ impl Display for Png {
     }
 }
 
-#[derive(Debug)]
-pub struct PngDecodeError {
-    reason: String,
-}
-
-impl PngDecodeError {
-    fn boxed(reason: String) -> Box<Self> {
-        Box::new(Self { reason })
-    }
-}
-
-impl fmt::Display for PngDecodeError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Bad PNG {}", self.reason)
-    }
-}
-
-impl Error for PngDecodeError {}
-
-#[derive(Debug)]
-pub struct ChunkNotFoundError {
-    chunk_type: String,
-}
-
-impl Error for ChunkNotFoundError {}
-
-impl fmt::Display for ChunkNotFoundError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "Couldn't find chunk of type {}", self.chunk_type)
-    }
-}
-
 impl TryFrom<&[u8]> for Png {
-    type Error = Box<dyn std::error::Error>;
-
-    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(bytes);
-        let mut header = [0u8; 8];
-        reader.read_exact(&mut header)?;
-        if header != Png::STANDARD_HEADER {
-            return Err(PngDecodeError::boxed(format!(
-                "Bad header (received {:?}, expected {:?})",
-                header,
-                Png::STANDARD_HEADER
-            )));
-        }
+    type Error = Error;
 
-        let mut chunks: Vec<Chunk> = Vec::new();
-        let mut length_buffer: [u8; 4] = [0; 4];
-
-        while let Ok(()) = reader.read_exact(&mut length_buffer) {
-            let final_position = 4 + u32::from_be_bytes(length_buffer) + 4;
-            let mut buffer = vec![0; usize::try_from(final_position)?];
-            reader.read_exact(&mut buffer).unwrap();
-            let all_bytes: Vec<u8> = length_buffer
-                .iter()
-                .copied()
-                .chain(buffer.into_iter())
-                .collect();
-
-            let chunk = Chunk::try_from(all_bytes.as_slice())?;
-            chunks.push(chunk);
-        }
-
-        Ok(Png::from_chunks(chunks))
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Png::from_reader(bytes)
     }
 }
 
@@ -145,13 +389,11 @@ mod tests {
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -159,10 +401,7 @@ mod tests {
         Png::from_chunks(chunks)
     }
 
-    fn chunk_from_strings(
-        chunk_type: &str,
-        data: &str,
-    ) -> Result<Chunk, Box<dyn std::error::Error>> {
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
         use std::str::FromStr;
 
         let chunk_type = ChunkType::from_str(chunk_type)?;
@@ -237,6 +476,201 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_truncated_png() {
+        let mut bytes = Png::try_from(&PNG_FILE[..]).unwrap().as_bytes();
+        bytes.truncate(100);
+
+        let png = Png::try_from(bytes.as_ref());
+
+        assert!(matches!(png, Err(Error::Truncated { offset: 100 })));
+        assert!(Png::try_from(&PNG_FILE[..4]).is_err());
+    }
+
+    #[test]
+    fn test_parse_with() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"PK\x03\x04 appended zip");
+        assert!(matches!(
+            Png::parse_with(&bytes, &ParseOptions::strict()),
+            Err(Error::TrailingData { length: 17, .. })
+        ));
+        assert!(Png::parse_with(&bytes, &ParseOptions::lenient()).is_ok());
+
+        let options = ParseOptions {
+            max_chunks: 2,
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            Png::parse_with(&PNG_FILE, &options),
+            Err(Error::LimitExceeded { maximum: 2, .. })
+        ));
+        let options = ParseOptions {
+            max_chunk_size: 100,
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            Png::parse_with(&PNG_FILE, &options),
+            Err(Error::LimitExceeded { .. })
+        ));
+
+        let without_iend = &PNG_FILE[..PNG_FILE.len() - 12];
+        assert!(Png::parse_with(without_iend, &ParseOptions::default()).is_ok());
+        assert!(matches!(
+            Png::parse_with(without_iend, &ParseOptions::strict()),
+            Err(Error::Truncated { .. })
+        ));
+    }
+
+    #[test]
+    fn test_untrusted_limits() {
+        // A 2 GiB length field must fail on the limit, not try to read or allocate it
+        let mut bytes = Png::STANDARD_HEADER.to_vec();
+        bytes.extend_from_slice(&[0x7f, 0xff, 0xff, 0xff, b'I', b'D', b'A', b'T']);
+        assert!(matches!(
+            Png::parse_with(&bytes, &ParseOptions::untrusted()),
+            Err(Error::LimitExceeded {
+                limit: "chunk size",
+                ..
+            })
+        ));
+
+        let options = ParseOptions {
+            max_total_size: 1000,
+            ..ParseOptions::default()
+        };
+        assert!(matches!(
+            Png::parse_with(&PNG_FILE, &options),
+            Err(Error::LimitExceeded {
+                limit: "total size",
+                ..
+            })
+        ));
+
+        let png = Png::parse_with(&PNG_FILE, &ParseOptions::untrusted()).unwrap();
+        assert!(png.decode_image().is_ok());
+        let options = ParseOptions {
+            max_decompression_ratio: 2,
+            ..ParseOptions::default()
+        };
+        let png = Png::parse_with(&PNG_FILE, &options).unwrap();
+        assert!(matches!(
+            png.decode_image(),
+            Err(Error::LimitExceeded {
+                limit: "decompression ratio",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_ancillary_crc_errors() {
+        let chunks = [
+            Chunk::new(ChunkType::new(b"IHDR"), vec![0; 13]),
+            Chunk::new(ChunkType::new(b"tEXt"), b"Title\0dice".to_vec()),
+            Chunk::new(ChunkType::new(b"IEND"), Vec::new()),
+        ];
+        let mut bytes: Vec<u8> = Png::STANDARD_HEADER.to_vec();
+        bytes.extend(chunks.iter().flat_map(|c| c.as_bytes()));
+        bytes[8 + 25 + 8] ^= 0x20;
+
+        assert!(Png::parse_with(&bytes, &ParseOptions::strict()).is_err());
+        let png = Png::parse_with(&bytes, &ParseOptions::lenient()).unwrap();
+        assert!(!png.chunks()[1].has_valid_crc());
+        assert_eq!(png.as_bytes(), bytes);
+
+        bytes[8 + 8] ^= 1;
+        assert!(matches!(
+            Png::parse_with(&bytes, &ParseOptions::lenient()),
+            Err(Error::CrcMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_header_info() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.header_info().unwrap();
+        assert_eq!((ihdr.width, ihdr.height), (50, 50));
+        assert_eq!(ihdr.bit_depth, 8);
+        assert_eq!(ihdr.color_type, crate::ihdr::ColorType::Rgba);
+    }
+
+    #[test]
+    fn test_decode_image() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image = png.decode_image().unwrap();
+        assert_eq!((image.width, image.height), (50, 50));
+        assert_eq!(image.pixels.len(), 50 * 50 * 4);
+
+        let ihdr = png.header_info().unwrap();
+        let scanlines = image.to_scanlines(ihdr.interlace_method).unwrap();
+        let reencoded = Scanlines::unfilter(&ihdr, &scanlines.filter()).unwrap();
+        assert_eq!(
+            Image::from_scanlines(&ihdr, &reencoded, png.inflate_limits()).unwrap(),
+            image
+        );
+    }
+
+    #[test]
+    fn test_header_info_missing_or_misplaced() {
+        assert!(matches!(
+            testing_png().header_info(),
+            Err(Error::InvalidIhdr(_))
+        ));
+
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.remove_chunk("IHDR").unwrap();
+        png.insert_chunk(ihdr, InsertPosition::At(1));
+        assert!(png.header_info().is_err());
+    }
+
+    #[test]
+    fn test_text_entries() {
+        let mut png = structured_png();
+        png.set_text("Title", "Dice").unwrap();
+        png.set_text("Author", "\u{30b5}\u{30a4}").unwrap();
+        png.set_text("Title", "More dice").unwrap();
+
+        let entries = png.text_entries().unwrap();
+        let pairs: Vec<(&str, &str)> = entries.iter().map(|e| (e.keyword(), e.text())).collect();
+        assert_eq!(
+            pairs,
+            [("Author", "\u{30b5}\u{30a4}"), ("Title", "More dice")]
+        );
+        assert_eq!(chunk_types(&png), ["IHDR", "IDAT", "iTXt", "tEXt", "IEND"]);
+    }
+
+    #[test]
+    fn test_text_bomb_within_parse_limits() {
+        let mut png = structured_png();
+        let bomb = crate::text::CompressedText::new("Bomb", &"0".repeat(1 << 20));
+        png.insert_chunk(bomb.to_chunk().unwrap(), InsertPosition::BeforeIend);
+        let bytes = png.as_bytes();
+
+        assert_eq!(
+            Png::try_from(&bytes[..])
+                .unwrap()
+                .text_entries()
+                .unwrap()
+                .len(),
+            1
+        );
+        let png = Png::parse_with(&bytes, &ParseOptions::untrusted()).unwrap();
+        assert!(matches!(
+            png.text_entries(),
+            Err(Error::LimitExceeded {
+                limit: "decompression ratio",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_text_entries_from_image_file() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.text_entries().unwrap().is_empty());
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -261,6 +695,69 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn structured_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "pixels").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ])
+    }
+
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut png = structured_png();
+        png.insert_chunk(
+            chunk_from_strings("ruSt", "Message").unwrap(),
+            InsertPosition::BeforeIend,
+        );
+        assert_eq!(chunk_types(&png), ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk_after_ihdr() {
+        let mut png = structured_png();
+        png.insert_chunk(
+            chunk_from_strings("ruSt", "Message").unwrap(),
+            InsertPosition::AfterIhdr,
+        );
+        assert_eq!(chunk_types(&png), ["IHDR", "ruSt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk_before_first_idat() {
+        let mut png = structured_png();
+        png.insert_chunk(
+            chunk_from_strings("IDAT", "more pixels").unwrap(),
+            InsertPosition::At(2),
+        );
+        png.insert_chunk(
+            chunk_from_strings("ruSt", "Message").unwrap(),
+            InsertPosition::BeforeFirstIdat,
+        );
+        assert_eq!(chunk_types(&png), ["IHDR", "ruSt", "IDAT", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_insert_chunk_without_iend() {
+        let mut png = testing_png();
+        png.insert_chunk(
+            chunk_from_strings("ruSt", "Message").unwrap(),
+            InsertPosition::BeforeIend,
+        );
+        png.insert_chunk(
+            chunk_from_strings("TeSt", "Message").unwrap(),
+            InsertPosition::At(100),
+        );
+        assert_eq!(chunk_types(&png), ["FrSt", "miDl", "LASt", "ruSt", "TeSt"]);
+    }
+
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
@@ -280,10 +777,42 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_trailing_data() {
+        let legacy = Chunk::new(ChunkType::new(b"ruSt"), b"After IEND".to_vec());
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(&legacy.as_bytes());
+        bytes.extend_from_slice(b"PK\x03\x04 appended zip");
+
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+        assert_eq!(png.trailing_data(), &bytes[PNG_FILE.len()..]);
+        assert_eq!(png.as_bytes(), bytes);
+
+        let mut written = Vec::new();
+        png.write_to(&mut written).unwrap();
+        assert_eq!(written, bytes);
+        assert!(Png::try_from(&PNG_FILE[..])
+            .unwrap()
+            .trailing_data()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_write_to() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut actual = Vec::new();
+        png.write_to(&mut actual).unwrap();
+        assert_eq!(actual, PNG_FILE.to_vec());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
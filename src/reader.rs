@@ -0,0 +1,469 @@
+use std::io::{self, Read};
+
+use crc::crc32::{self, Hasher32};
+
+use crate::chunk::{checksum, Chunk, MAXIMUM_LENGTH};
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::png::{ParseOptions, Png};
+use crate::repair::Damage;
+
+/// Size of the buffer used when skipping over chunk data
+const SKIP_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reads chunks one at a time from any `Read`, so a PNG never has to be held in memory whole.
+/// Reading stops at IEND, whatever follows is left for `read_remaining`.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    offset: usize,
+    /// Number of chunks read or skipped so far
+    index: usize,
+    /// Offset of the chunk being read
+    chunk_start: usize,
+    /// Whether IEND has been read or skipped
+    ended: bool,
+    done: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    /// Reads and checks the PNG signature, leaving the reader at the first chunk
+    pub fn new(mut reader: R) -> Result<ChunkReader<R>> {
+        let mut header = [0u8; 8];
+        let read = read_up_to(&mut reader, &mut header)?;
+        if read < header.len() {
+            return Err(Error::Truncated { offset: read });
+        }
+        if header != Png::STANDARD_HEADER {
+            return Err(Error::BadSignature(header));
+        }
+
+        Ok(ChunkReader {
+            reader,
+            offset: header.len(),
+            index: 0,
+            chunk_start: header.len(),
+            ended: false,
+            done: false,
+        })
+    }
+
+    /// Number of bytes consumed so far, including the signature
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Whether the reader has passed IEND, so only trailing data is left
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Reads the next chunk, or `None` after IEND or once the input ends on a chunk boundary
+    pub fn read_chunk(&mut self) -> Result<Option<Chunk>> {
+        self.read_chunk_with(&ParseOptions::default())
+    }
+
+    /// Like `read_chunk`, but applies the chunk size and count limits and CRC tolerance of
+    /// `options`. Limits are checked from the chunk header, before any data is read.
+    pub fn read_chunk_with(&mut self, options: &ParseOptions) -> Result<Option<Chunk>> {
+        if self.ended {
+            return Ok(None);
+        }
+        let (length, chunk_type) = match self.read_chunk_header()? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        if self.index >= options.max_chunks {
+            return Err(Error::LimitExceeded {
+                limit: "chunk count",
+                maximum: options.max_chunks as u64,
+            });
+        }
+        if length > options.max_chunk_size {
+            return Err(Error::LimitExceeded {
+                limit: "chunk size",
+                maximum: u64::from(options.max_chunk_size),
+            });
+        }
+
+        let tolerate_crc = options.ignore_ancillary_crc_errors && !chunk_type.is_critical();
+        self.read_chunk_data(length, chunk_type, tolerate_crc)
+            .map(Some)
+    }
+
+    /// Reads the remaining chunks up to IEND like a parse that can't fail on bad data:
+    /// chunks with a wrong CRC are kept as stored, and a cut-off or unreadable tail ends
+    /// the file. Only I/O errors are returned.
+    pub fn read_lenient(&mut self) -> Result<(Vec<Chunk>, Vec<Damage>)> {
+        let mut chunks = Vec::new();
+        let mut damage = Vec::new();
+        self.done = true;
+
+        while !self.ended {
+            let start = self.offset;
+            let (length, chunk_type) = match self.read_chunk_header() {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(Error::Io(why)) => return Err(Error::Io(why)),
+                Err(why) => {
+                    let mut rest = Vec::new();
+                    self.offset += self.reader.read_to_end(&mut rest)?;
+                    damage.push(Damage::UnreadableTail {
+                        offset: start,
+                        length: self.offset - start,
+                        reason: why.to_string(),
+                    });
+                    break;
+                }
+            };
+
+            let mut data = Vec::new();
+            self.offset += (&mut self.reader)
+                .take(u64::from(length))
+                .read_to_end(&mut data)?;
+            let mut crc = [0u8; 4];
+            let read = read_up_to(&mut self.reader, &mut crc)?;
+            self.offset += read;
+            if data.len() < length as usize || read < crc.len() {
+                damage.push(Damage::TruncatedChunk {
+                    offset: start,
+                    chunk_type,
+                    length,
+                    data,
+                });
+                break;
+            }
+
+            let chunk = Chunk::from_parts_unchecked(chunk_type, data, u32::from_be_bytes(crc))
+                .located_at(start);
+            if !chunk.has_valid_crc() {
+                damage.push(Damage::CrcMismatch {
+                    index: chunks.len(),
+                    chunk_type: chunk.chunk_type().clone(),
+                    expected: chunk.crc(),
+                    actual: checksum(chunk.chunk_type(), chunk.data()),
+                });
+            }
+            self.ended = chunk.chunk_type().to_string() == "IEND";
+            chunks.push(chunk);
+        }
+        Ok((chunks, damage))
+    }
+
+    /// Reads whatever is left of the input as plain bytes, such as data appended after IEND,
+    /// at most one more than `maximum` so callers can tell when it goes past a limit
+    pub fn read_remaining(&mut self, maximum: u64) -> Result<Vec<u8>> {
+        let mut rest = Vec::new();
+        self.offset += (&mut self.reader)
+            .take(maximum.saturating_add(1))
+            .read_to_end(&mut rest)?;
+        self.done = true;
+        Ok(rest)
+    }
+
+    /// Scans forward to the first chunk of `chunk_type`, checking but not keeping the data of
+    /// every chunk before it. Gives up at IEND.
+    pub fn find_chunk(&mut self, chunk_type: &str) -> Result<Option<Chunk>> {
+        while !self.ended {
+            let (length, current_type) = match self.read_chunk_header()? {
+                Some(header) => header,
+                None => return Ok(None),
+            };
+
+            if current_type.to_string() == chunk_type {
+                return self.read_chunk_data(length, current_type, false).map(Some);
+            }
+
+            self.skip_chunk_data(length, current_type)?;
+        }
+        Ok(None)
+    }
+
+    fn read_chunk_data(
+        &mut self,
+        length: u32,
+        chunk_type: ChunkType,
+        tolerate_crc: bool,
+    ) -> Result<Chunk> {
+        let mut chunk_data = Vec::new();
+        let read = (&mut self.reader)
+            .take(u64::from(length))
+            .read_to_end(&mut chunk_data)?;
+        self.offset += read;
+        if read < length as usize {
+            return Err(Error::Truncated {
+                offset: self.offset,
+            });
+        }
+
+        let crc = self.read_u32()?;
+        let chunk =
+            Chunk::from_parts_unchecked(chunk_type, chunk_data, crc).located_at(self.chunk_start);
+        if !tolerate_crc && !chunk.has_valid_crc() {
+            return Err(Error::CrcMismatch {
+                chunk_type: chunk.chunk_type().clone(),
+                expected: crc,
+                actual: checksum(chunk.chunk_type(), chunk.data()),
+                index: Some(self.index),
+                offset: Some(self.chunk_start),
+            });
+        }
+        self.index += 1;
+        self.ended = chunk.chunk_type().to_string() == "IEND";
+        Ok(chunk)
+    }
+
+    fn skip_chunk_data(&mut self, length: u32, chunk_type: ChunkType) -> Result<()> {
+        let ended = chunk_type.to_string() == "IEND";
+        let mut digest = crc32::Digest::new(crc32::IEEE);
+        digest.write(&chunk_type.bytes());
+
+        let mut buffer = [0u8; SKIP_BUFFER_SIZE];
+        let mut remaining = length as usize;
+        while remaining > 0 {
+            let wanted = remaining.min(buffer.len());
+            let read = read_up_to(&mut self.reader, &mut buffer[..wanted])?;
+            self.offset += read;
+            if read < wanted {
+                return Err(Error::Truncated {
+                    offset: self.offset,
+                });
+            }
+            digest.write(&buffer[..read]);
+            remaining -= read;
+        }
+
+        let crc = self.read_u32()?;
+        if crc != digest.sum32() {
+            return Err(Error::CrcMismatch {
+                chunk_type,
+                expected: crc,
+                actual: digest.sum32(),
+                index: Some(self.index),
+                offset: Some(self.chunk_start),
+            });
+        }
+
+        self.index += 1;
+        self.ended = ended;
+        Ok(())
+    }
+
+    fn read_chunk_header(&mut self) -> Result<Option<(u32, ChunkType)>> {
+        self.chunk_start = self.offset;
+        let mut length_buffer = [0u8; 4];
+        let read = read_up_to(&mut self.reader, &mut length_buffer)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        self.offset += read;
+        if read < length_buffer.len() {
+            return Err(Error::Truncated {
+                offset: self.offset,
+            });
+        }
+
+        let length = u32::from_be_bytes(length_buffer);
+        if length > MAXIMUM_LENGTH {
+            return Err(Error::LengthOverflow(u64::from(length)));
+        }
+
+        let chunk_type = ChunkType::try_from(self.read_u32()?.to_be_bytes())?;
+        Ok(Some((length, chunk_type)))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        let mut buffer = [0u8; 4];
+        let read = read_up_to(&mut self.reader, &mut buffer)?;
+        self.offset += read;
+        if read < buffer.len() {
+            return Err(Error::Truncated {
+                offset: self.offset,
+            });
+        }
+        Ok(u32::from_be_bytes(buffer))
+    }
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let next = self.read_chunk().transpose();
+        if !matches!(next, Some(Ok(_))) {
+            self.done = true;
+        }
+        next
+    }
+}
+
+// @notice: Fills as much of `buffer` as the reader allows, returning how much was read
+fn read_up_to<R: Read>(reader: &mut R, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(why) if why.kind() == io::ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = [
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![1; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![2; 20_000]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"Message".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ];
+
+        Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(chunks.iter().flat_map(|c| c.as_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_iterate_chunks() {
+        let bytes = testing_bytes();
+        let reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let types: Vec<String> = reader
+            .map(|c| c.unwrap().chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_find_chunk() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let chunk = reader.find_chunk("ruSt").unwrap().unwrap();
+        assert_eq!(chunk.data_as_string().unwrap(), "Message");
+        assert_eq!(reader.offset(), bytes.len() - 12);
+        assert!(reader.find_chunk("ruSt").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_chunk_checks_skipped_crc() {
+        let mut bytes = testing_bytes();
+        bytes[8 + 25 + 100] ^= 1;
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        let error = reader.find_chunk("ruSt").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::CrcMismatch {
+                index: Some(1),
+                offset: Some(33),
+                ..
+            }
+        ));
+        assert!(error
+            .to_string()
+            .starts_with("CRC mismatch in IDAT chunk 1 at byte 0x21"));
+    }
+
+    #[test]
+    fn test_stops_at_iend() {
+        let mut bytes = testing_bytes();
+        bytes.extend_from_slice(b"PK\x03\x04 appended zip");
+
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.by_ref().take(4).all(|c| c.is_ok()));
+        assert!(reader.next().is_none());
+        assert!(reader.ended());
+        assert_eq!(
+            reader.read_remaining(u64::MAX).unwrap(),
+            b"PK\x03\x04 appended zip"
+        );
+
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.find_chunk("tEXt").unwrap().is_none());
+        assert!(reader.ended());
+        assert_eq!(
+            reader.read_remaining(u64::MAX).unwrap(),
+            b"PK\x03\x04 appended zip"
+        );
+    }
+
+    #[test]
+    fn test_chunk_count_checked_before_data() {
+        let bytes = testing_bytes();
+        let options = ParseOptions {
+            max_chunks: 1,
+            ..ParseOptions::default()
+        };
+        let mut reader = ChunkReader::new(bytes.as_slice()).unwrap();
+        assert!(reader.read_chunk_with(&options).unwrap().is_some());
+        assert!(matches!(
+            reader.read_chunk_with(&options),
+            Err(Error::LimitExceeded {
+                limit: "chunk count",
+                maximum: 1
+            })
+        ));
+        // Only the IDAT header was read, none of its 20,000 bytes of data
+        assert_eq!(reader.offset(), 33 + 8);
+    }
+
+    #[test]
+    fn test_chunk_offsets() {
+        let bytes = testing_bytes();
+        let offsets: Vec<Option<usize>> = ChunkReader::new(bytes.as_slice())
+            .unwrap()
+            .map(|c| c.unwrap().offset())
+            .collect();
+        assert_eq!(offsets, [Some(8), Some(33), Some(20_045), Some(20_064)]);
+
+        let mut bytes = bytes;
+        bytes[20_045 + 8] ^= 1;
+        let error = ChunkReader::new(bytes.as_slice())
+            .unwrap()
+            .find_map(|c| c.err())
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "CRC mismatch in ruSt chunk 2 at byte 0x4e4d (expected {:#010x}, got {:#010x})",
+                Chunk::new(ChunkType::new(b"ruSt"), b"Message".to_vec()).crc(),
+                Chunk::new(ChunkType::new(b"ruSt"), b"Lessage".to_vec()).crc(),
+            )
+        );
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let bytes = testing_bytes();
+        let mut reader = ChunkReader::new(&bytes[..1000]).unwrap();
+        assert!(reader.next().unwrap().is_ok());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Error::Truncated { offset: 1000 }))
+        ));
+        assert!(reader.next().is_none());
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let mut bytes = testing_bytes();
+        bytes[0] = 13;
+        assert!(matches!(
+            ChunkReader::new(bytes.as_slice()),
+            Err(Error::BadSignature(_))
+        ));
+    }
+}
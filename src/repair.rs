@@ -0,0 +1,283 @@
+// Reads damaged files as far as possible and patches them back into valid PNGs
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+/// Something a lenient parse read past instead of failing
+#[derive(Debug, Clone, PartialEq)]
+pub enum Damage {
+    /// The chunk at `index` was kept, but its stored CRC doesn't match its contents
+    CrcMismatch {
+        index: usize,
+        chunk_type: ChunkType,
+        expected: u32,
+        actual: u32,
+    },
+    /// The input ends inside the data or CRC of a chunk starting at `offset`, `data` holds
+    /// as much of its data as was there
+    TruncatedChunk {
+        offset: usize,
+        chunk_type: ChunkType,
+        length: u32,
+        data: Vec<u8>,
+    },
+    /// Bytes from `offset` to the end that don't form a chunk header
+    UnreadableTail {
+        offset: usize,
+        length: usize,
+        reason: String,
+    },
+}
+
+/// A change `repair` made
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "fix", rename_all = "snake_case")]
+pub enum Fix {
+    /// Replaced a wrong CRC with the one matching the chunk's contents
+    RecomputedCrc {
+        index: usize,
+        chunk_type: String,
+        stored: u32,
+        computed: u32,
+    },
+    /// Kept the part of a cut-off IDAT chunk that was there, so decoders get what they can
+    ShortenedChunk {
+        index: usize,
+        chunk_type: String,
+        length: u32,
+        kept: usize,
+    },
+    /// Dropped a cut-off chunk
+    DroppedChunk {
+        offset: usize,
+        chunk_type: String,
+        length: u32,
+    },
+    /// Dropped trailing bytes that weren't a chunk
+    DroppedBytes {
+        offset: usize,
+        length: usize,
+        reason: String,
+    },
+    /// Appended the missing IEND chunk
+    AddedIend { index: usize },
+}
+
+impl Display for Fix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fix::RecomputedCrc {
+                index,
+                chunk_type,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "Recomputed CRC of {} chunk {}: {:#010x} -> {:#010x}",
+                chunk_type, index, stored, computed
+            ),
+            Fix::ShortenedChunk {
+                index,
+                chunk_type,
+                length,
+                kept,
+            } => write!(
+                f,
+                "Shortened truncated {} chunk {} from {} to {} bytes",
+                chunk_type, index, length, kept
+            ),
+            Fix::DroppedChunk {
+                offset,
+                chunk_type,
+                length,
+            } => write!(
+                f,
+                "Dropped truncated {} chunk of {} bytes at byte {}",
+                chunk_type, length, offset
+            ),
+            Fix::DroppedBytes {
+                offset,
+                length,
+                reason,
+            } => write!(
+                f,
+                "Dropped {} unreadable bytes at byte {}: {}",
+                length, offset, reason
+            ),
+            Fix::AddedIend { index } => write!(f, "Added missing IEND chunk {}", index),
+        }
+    }
+}
+
+/// Fixes the `damage` found while leniently parsing `png`, then appends IEND if it's
+/// missing, returning every change made
+pub fn repair(png: &mut Png, damage: Vec<Damage>) -> Vec<Fix> {
+    let mut fixes = Vec::new();
+    let ended = png.chunk_by_type("IEND").is_some();
+
+    for damage in damage {
+        match damage {
+            Damage::CrcMismatch {
+                index,
+                chunk_type,
+                expected,
+                actual,
+            } => {
+                let data = png.chunks()[index].data().to_vec();
+                png.replace_chunk(index, Chunk::new(chunk_type.clone(), data));
+                fixes.push(Fix::RecomputedCrc {
+                    index,
+                    chunk_type: chunk_type.to_string(),
+                    stored: expected,
+                    computed: actual,
+                });
+            }
+            // Part of the image beats none of it, anything else cut short is meaningless
+            Damage::TruncatedChunk {
+                chunk_type,
+                length,
+                data,
+                ..
+            } if !ended && !data.is_empty() && chunk_type.to_string() == "IDAT" => {
+                let kept = data.len();
+                png.append_chunk(Chunk::new(chunk_type.clone(), data));
+                fixes.push(Fix::ShortenedChunk {
+                    index: png.chunks().len() - 1,
+                    chunk_type: chunk_type.to_string(),
+                    length,
+                    kept,
+                });
+            }
+            Damage::TruncatedChunk {
+                offset,
+                chunk_type,
+                length,
+                ..
+            } => fixes.push(Fix::DroppedChunk {
+                offset,
+                chunk_type: chunk_type.to_string(),
+                length,
+            }),
+            Damage::UnreadableTail {
+                offset,
+                length,
+                reason,
+            } => fixes.push(Fix::DroppedBytes {
+                offset,
+                length,
+                reason,
+            }),
+        }
+    }
+
+    if !ended {
+        png.append_chunk(Chunk::new(ChunkType::new(b"IEND"), Vec::new()));
+        fixes.push(Fix::AddedIend {
+            index: png.chunks().len() - 1,
+        });
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = [
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![1; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"Message".to_vec()),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![2; 100]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ];
+        Png::STANDARD_HEADER
+            .iter()
+            .copied()
+            .chain(chunks.iter().flat_map(|c| c.as_bytes()))
+            .collect()
+    }
+
+    fn lenient(bytes: &[u8]) -> (Png, Vec<Damage>) {
+        Png::from_reader_lenient(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_recompute_crc() {
+        let mut bytes = testing_bytes();
+        bytes[8 + 25 + 8] ^= 0x20;
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let (mut png, damage) = lenient(&bytes);
+        assert!(!png.chunks()[1].has_valid_crc());
+        assert_eq!(png.as_bytes(), bytes);
+
+        let fixes = repair(&mut png, damage);
+        assert!(matches!(fixes[..], [Fix::RecomputedCrc { index: 1, .. }]));
+        assert_eq!(png.chunks()[1].data(), b"message");
+        assert!(Png::try_from(png.as_bytes().as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_truncated_image_data() {
+        let bytes = testing_bytes();
+        let (mut png, damage) = lenient(&bytes[..8 + 25 + 19 + 50]);
+        assert_eq!(png.chunks().len(), 2);
+
+        let fixes = repair(&mut png, damage);
+        assert_eq!(
+            fixes,
+            [
+                Fix::ShortenedChunk {
+                    index: 2,
+                    chunk_type: String::from("IDAT"),
+                    length: 100,
+                    kept: 42,
+                },
+                Fix::AddedIend { index: 3 },
+            ]
+        );
+        assert_eq!(png.chunks()[2].data(), [2; 42]);
+        assert!(Png::try_from(png.as_bytes().as_slice()).is_ok());
+    }
+
+    #[test]
+    fn test_drop_unreadable_tail() {
+        let bytes = testing_bytes();
+        let mut damaged = bytes[..8 + 25 + 19].to_vec();
+        damaged.extend_from_slice(&[0, 0, 0, 4, b'1', b'2', b'3', b'4', 9, 9]);
+
+        let (mut png, damage) = lenient(&damaged);
+        let fixes = repair(&mut png, damage);
+        assert!(matches!(
+            &fixes[..],
+            [
+                Fix::DroppedBytes {
+                    offset: 52,
+                    length: 10,
+                    ..
+                },
+                Fix::AddedIend { index: 2 },
+            ]
+        ));
+
+        let (mut png, damage) = lenient(&bytes[..bytes.len() - 2]);
+        let fixes = repair(&mut png, damage);
+        assert!(matches!(
+            &fixes[..],
+            [Fix::DroppedChunk { .. }, Fix::AddedIend { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_intact_png_needs_no_fixes() {
+        let bytes = testing_bytes();
+        let (mut png, damage) = lenient(&bytes);
+        assert!(damage.is_empty());
+        assert!(repair(&mut png, damage).is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use chacha20poly1305::aead::OsRng;
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto::{parse_key, to_hex};
+use crate::error::{Error, Result};
+use crate::png::Png;
+
+/// Ancillary, private, unsafe-to-copy: the signature depends on the image data
+pub const CHUNK_TYPE: &str = "siGN";
+
+const VERSION: u8 = 1;
+/// Prepended to the signed manifest so the signature can't be replayed elsewhere
+const DOMAIN: &[u8] = b"pngme/signature/v1";
+const DIGEST_LENGTH: usize = 32;
+const SIGNATURE_LENGTH: usize = 64;
+const SIGNING_KEY_PREFIX: &str = "pngme-signing-";
+const VERIFYING_KEY_PREFIX: &str = "pngme-verifying-";
+
+/// A secret Ed25519 key that signs images
+pub struct Signer(SigningKey);
+
+/// The public half of a `Signer`
+#[derive(Clone, PartialEq, Eq)]
+pub struct Verifier(VerifyingKey);
+
+/// What happened to a chunk covered by a signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStatus {
+    /// Unchanged since signing
+    Intact,
+    /// Present but its bytes changed
+    Altered,
+    /// Signed but no longer in the image
+    Missing,
+    /// Of a covered type but added after signing
+    Added,
+}
+
+/// One chunk the signature covers, or should cover
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CoveredChunk {
+    /// Position in the current image, `None` when missing
+    pub index: Option<usize>,
+    pub chunk_type: String,
+    pub status: ChunkStatus,
+}
+
+/// Result of checking a signature against an image
+#[derive(Debug, Clone)]
+pub struct Verification {
+    pub signer: Verifier,
+    /// Whether the signed manifest itself is authentic
+    pub signature_valid: bool,
+    pub chunks: Vec<CoveredChunk>,
+}
+
+impl Verification {
+    /// The manifest is authentic and every covered chunk is intact
+    pub fn is_valid(&self) -> bool {
+        self.signature_valid && self.chunks.iter().all(|c| c.status == ChunkStatus::Intact)
+    }
+}
+
+impl Signer {
+    pub fn generate() -> Signer {
+        Signer(SigningKey::generate(&mut OsRng))
+    }
+
+    pub fn verifier(&self) -> Verifier {
+        Verifier(self.0.verifying_key())
+    }
+}
+
+impl Display for Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", SIGNING_KEY_PREFIX, to_hex(&self.0.to_bytes()))
+    }
+}
+
+impl FromStr for Signer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = parse_key(s, SIGNING_KEY_PREFIX)?;
+        Ok(Signer(SigningKey::from_bytes(&bytes)))
+    }
+}
+
+impl Display for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", VERIFYING_KEY_PREFIX, to_hex(self.0.as_bytes()))
+    }
+}
+
+impl fmt::Debug for Verifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Verifier({})", self)
+    }
+}
+
+impl FromStr for Verifier {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = parse_key(s, VERIFYING_KEY_PREFIX)?;
+        VerifyingKey::from_bytes(&bytes)
+            .map(Verifier)
+            .map_err(|why| Error::InvalidKey(why.to_string()))
+    }
+}
+
+/// All critical chunk types in `png`, plus `message_type` when the image has such a chunk
+pub fn default_covered_types(png: &Png, message_type: &ChunkType) -> Vec<ChunkType> {
+    let mut types: Vec<ChunkType> = Vec::new();
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type();
+        if (chunk_type.is_critical() || chunk_type == message_type) && !types.contains(chunk_type) {
+            types.push(chunk_type.clone());
+        }
+    }
+    types
+}
+
+/// Signs the chunks of `png` whose type is in `covered_types`, returning the data for a
+/// signature chunk: version, signer's public key, the covered types, a type and SHA-256
+/// digest of each covered chunk's serialized bytes, then an Ed25519 signature over all of it.
+pub fn sign(png: &Png, signer: &Signer, covered_types: &[ChunkType]) -> Result<Vec<u8>> {
+    let type_count =
+        u8::try_from(covered_types.len()).map_err(|_| invalid("too many covered chunk types"))?;
+    let covered = covered_chunks(png, covered_types);
+    let entry_count =
+        u32::try_from(covered.len()).map_err(|_| invalid("too many covered chunks"))?;
+
+    let mut manifest = vec![VERSION];
+    manifest.extend_from_slice(signer.verifier().0.as_bytes());
+    manifest.push(type_count);
+    for chunk_type in covered_types {
+        manifest.extend_from_slice(&chunk_type.bytes());
+    }
+    manifest.extend_from_slice(&entry_count.to_be_bytes());
+    for (_, chunk) in covered {
+        manifest.extend_from_slice(&chunk.chunk_type().bytes());
+        manifest.extend_from_slice(&digest(chunk));
+    }
+
+    let signature = signer.0.sign(&[DOMAIN, &manifest].concat());
+    manifest.extend_from_slice(&signature.to_bytes());
+    Ok(manifest)
+}
+
+/// Checks signature data made by `sign` against `png`, reporting every covered chunk
+pub fn verify(png: &Png, data: &[u8]) -> Result<Verification> {
+    let manifest_length = data
+        .len()
+        .checked_sub(SIGNATURE_LENGTH)
+        .ok_or_else(|| invalid("signature is truncated"))?;
+    let (manifest, signature) = data.split_at(manifest_length);
+
+    let mut fields = Fields { bytes: manifest };
+    let version = fields.take(1)?[0];
+    if version != VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    let signer = VerifyingKey::from_bytes(&fields.array()?)
+        .map(Verifier)
+        .map_err(|why| Error::InvalidKey(why.to_string()))?;
+
+    let mut covered_types = Vec::new();
+    for _ in 0..fields.take(1)?[0] {
+        covered_types.push(ChunkType::try_from(fields.array::<4>()?)?);
+    }
+    let mut entries = Vec::new();
+    for _ in 0..u32::from_be_bytes(fields.array()?) {
+        let chunk_type = ChunkType::try_from(fields.array::<4>()?)?;
+        entries.push((chunk_type, fields.array::<DIGEST_LENGTH>()?));
+    }
+    if !fields.bytes.is_empty() {
+        return Err(invalid("unexpected data after the manifest"));
+    }
+
+    let signature = Signature::from_slice(signature).map_err(|why| invalid(&why.to_string()))?;
+    let signature_valid = signer
+        .0
+        .verify_strict(&[DOMAIN, manifest].concat(), &signature)
+        .is_ok();
+
+    let current = covered_chunks(png, &covered_types);
+    let chunks = match_entries(&entries, &current);
+
+    Ok(Verification {
+        signer,
+        signature_valid,
+        chunks,
+    })
+}
+
+// @notice: Lines signed entries up with the current chunks along their longest common
+// subsequence, so an inserted or removed chunk doesn't shift every later one. Between two
+// matches, a leftover entry and chunk of the same type pair up as altered, any other chunk
+// was added and any other entry is missing.
+fn match_entries(
+    entries: &[(ChunkType, [u8; DIGEST_LENGTH])],
+    current: &[(usize, &Chunk)],
+) -> Vec<CoveredChunk> {
+    let same = |entry: &(ChunkType, [u8; DIGEST_LENGTH]), chunk: &Chunk| {
+        *chunk.chunk_type() == entry.0 && digest(chunk) == entry.1
+    };
+
+    // lengths[i][j] is the longest common subsequence of entries[i..] and current[j..]
+    let mut lengths = vec![vec![0usize; current.len() + 1]; entries.len() + 1];
+    for i in (0..entries.len()).rev() {
+        for j in (0..current.len()).rev() {
+            lengths[i][j] = match same(&entries[i], current[j].1) {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut chunks = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let (mut gap_start_i, mut gap_start_j) = (0, 0);
+    loop {
+        let matched = i < entries.len() && j < current.len() && same(&entries[i], current[j].1);
+        let finished = i == entries.len() && j == current.len();
+        if matched || finished {
+            report_gap(
+                &entries[gap_start_i..i],
+                &current[gap_start_j..j],
+                &mut chunks,
+            );
+            if finished {
+                return chunks;
+            }
+            chunks.push(CoveredChunk {
+                index: Some(current[j].0),
+                chunk_type: current[j].1.chunk_type().to_string(),
+                status: ChunkStatus::Intact,
+            });
+            i += 1;
+            j += 1;
+            gap_start_i = i;
+            gap_start_j = j;
+        } else if j == current.len()
+            || (i < entries.len() && lengths[i + 1][j] >= lengths[i][j + 1])
+        {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+}
+
+// @notice: Reports the unmatched entries and chunks between two intact ones
+fn report_gap(
+    entries: &[(ChunkType, [u8; DIGEST_LENGTH])],
+    current: &[(usize, &Chunk)],
+    chunks: &mut Vec<CoveredChunk>,
+) {
+    let mut paired = vec![false; entries.len()];
+    for (index, chunk) in current {
+        let entry = (0..entries.len()).find(|&e| !paired[e] && entries[e].0 == *chunk.chunk_type());
+        if let Some(entry) = entry {
+            paired[entry] = true;
+        }
+        chunks.push(CoveredChunk {
+            index: Some(*index),
+            chunk_type: chunk.chunk_type().to_string(),
+            status: match entry {
+                Some(_) => ChunkStatus::Altered,
+                None => ChunkStatus::Added,
+            },
+        });
+    }
+    for (entry, _) in entries.iter().zip(&paired).filter(|(_, paired)| !**paired) {
+        chunks.push(CoveredChunk {
+            index: None,
+            chunk_type: entry.0.to_string(),
+            status: ChunkStatus::Missing,
+        });
+    }
+}
+
+// @notice: Chunks of the covered types in file order, never including signature chunks
+fn covered_chunks<'a>(png: &'a Png, covered_types: &[ChunkType]) -> Vec<(usize, &'a Chunk)> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| {
+            c.chunk_type().to_string() != CHUNK_TYPE && covered_types.contains(c.chunk_type())
+        })
+        .collect()
+}
+
+fn digest(chunk: &Chunk) -> [u8; DIGEST_LENGTH] {
+    Sha256::digest(chunk.as_bytes()).into()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidSignature(String::from(reason))
+}
+
+/// Reads manifest fields front to back
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < length {
+            return Err(invalid("manifest is truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::InsertPosition;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.as_bytes().to_vec(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("tEXt", "Title\0Dice"),
+            chunk("IDAT", "pixels"),
+            chunk("IDAT", "more pixels"),
+            chunk("ruSt", "Message"),
+            chunk("IEND", ""),
+        ])
+    }
+
+    fn signed_png(signer: &Signer) -> Png {
+        let mut png = testing_png();
+        let types = default_covered_types(&png, &ChunkType::from_str("ruSt").unwrap());
+        let data = sign(&png, signer, &types).unwrap();
+        png.insert_chunk(
+            Chunk::new(ChunkType::from_str(CHUNK_TYPE).unwrap(), data),
+            InsertPosition::BeforeIend,
+        );
+        png
+    }
+
+    fn statuses(verification: &Verification) -> Vec<(String, ChunkStatus)> {
+        verification
+            .chunks
+            .iter()
+            .map(|c| (c.chunk_type.clone(), c.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_covered_types() {
+        let types: Vec<String> =
+            default_covered_types(&testing_png(), &ChunkType::from_str("ruSt").unwrap())
+                .iter()
+                .map(|t| t.to_string())
+                .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = Signer::generate();
+        let png = signed_png(&signer);
+
+        let data = png.chunk_by_type(CHUNK_TYPE).unwrap().data();
+        let verification = verify(&png, data).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.signer, signer.verifier());
+        assert_eq!(verification.chunks.len(), 5);
+    }
+
+    #[test]
+    fn test_verify_reports_altered_and_added_chunks() {
+        let signer = Signer::generate();
+        let mut png = signed_png(&signer);
+        let data = png.chunk_by_type(CHUNK_TYPE).unwrap().data().to_vec();
+
+        png.remove_chunk("ruSt").unwrap();
+        png.insert_chunk(chunk("ruSt", "Forged"), InsertPosition::At(4));
+        png.insert_chunk(chunk("IDAT", "extra"), InsertPosition::At(4));
+
+        let verification = verify(&png, &data).unwrap();
+        assert!(verification.signature_valid);
+        assert!(!verification.is_valid());
+        assert_eq!(
+            statuses(&verification),
+            [
+                (String::from("IHDR"), ChunkStatus::Intact),
+                (String::from("IDAT"), ChunkStatus::Intact),
+                (String::from("IDAT"), ChunkStatus::Intact),
+                (String::from("IDAT"), ChunkStatus::Added),
+                (String::from("ruSt"), ChunkStatus::Altered),
+                (String::from("IEND"), ChunkStatus::Intact),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_verify_reports_missing_chunks() {
+        let signer = Signer::generate();
+        let mut png = signed_png(&signer);
+        let data = png.chunk_by_type(CHUNK_TYPE).unwrap().data().to_vec();
+
+        png.remove_chunk("IDAT").unwrap();
+        let verification = verify(&png, &data).unwrap();
+        assert_eq!(
+            statuses(&verification),
+            [
+                (String::from("IHDR"), ChunkStatus::Intact),
+                (String::from("IDAT"), ChunkStatus::Missing),
+                (String::from("IDAT"), ChunkStatus::Intact),
+                (String::from("ruSt"), ChunkStatus::Intact),
+                (String::from("IEND"), ChunkStatus::Intact),
+            ]
+        );
+        assert_eq!(verification.chunks[1].index, None);
+    }
+
+    #[test]
+    fn test_uncovered_chunks_can_change() {
+        let signer = Signer::generate();
+        let mut png = signed_png(&signer);
+        let data = png.chunk_by_type(CHUNK_TYPE).unwrap().data().to_vec();
+
+        png.remove_chunk("tEXt").unwrap();
+        let verification = verify(&png, &data).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.chunks[1].index, Some(1));
+    }
+
+    #[test]
+    fn test_tampered_manifest() {
+        let signer = Signer::generate();
+        let png = signed_png(&signer);
+        let mut data = png.chunk_by_type(CHUNK_TYPE).unwrap().data().to_vec();
+
+        let last_digest_byte = data.len() - SIGNATURE_LENGTH - 1;
+        data[last_digest_byte] ^= 1;
+        let verification = verify(&png, &data).unwrap();
+        assert!(!verification.signature_valid);
+        assert!(!verification.is_valid());
+
+        assert!(matches!(
+            verify(&png, &data[..10]),
+            Err(Error::InvalidSignature(_))
+        ));
+    }
+
+    #[test]
+    fn test_key_round_trip() {
+        let signer = Signer::generate();
+        let parsed = Signer::from_str(&signer.to_string()).unwrap();
+        assert_eq!(parsed.verifier(), signer.verifier());
+        assert_eq!(
+            Verifier::from_str(&signer.verifier().to_string()).unwrap(),
+            signer.verifier()
+        );
+        assert!(Verifier::from_str(&signer.to_string()).is_err());
+    }
+}
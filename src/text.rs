@@ -0,0 +1,411 @@
+// Implementation of the textual chunks of PNG spec[http://www.libpng.org/pub/png/spec/1.2/PNG-Chunks.html#C.Anc-text]
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::{Error, Result};
+use crate::zlib::{deflate, inflate_limited, InflateLimits};
+
+/// Longest keyword the spec allows, in bytes
+const MAXIMUM_KEYWORD_LENGTH: usize = 79;
+
+/// A `tEXt` chunk: an uncompressed Latin-1 keyword/value pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// A `zTXt` chunk: a Latin-1 keyword/value pair with a deflated value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedText {
+    pub keyword: String,
+    pub text: String,
+}
+
+/// An `iTXt` chunk: a UTF-8 value with an optional language tag and translated keyword
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InternationalText {
+    pub keyword: String,
+    pub compressed: bool,
+    pub language_tag: String,
+    pub translated_keyword: String,
+    pub text: String,
+}
+
+/// Any of the three textual chunk kinds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextEntry {
+    Text(Text),
+    Compressed(CompressedText),
+    International(InternationalText),
+}
+
+impl Text {
+    pub const CHUNK_TYPE: &'static str = "tEXt";
+
+    pub fn new(keyword: &str, text: &str) -> Text {
+        Text {
+            keyword: String::from(keyword),
+            text: String::from(text),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = encode_keyword(&self.keyword)?;
+        data.extend(encode_latin1(&self.text)?);
+        Ok(Chunk::new(ChunkType::new(b"tEXt"), data))
+    }
+}
+
+impl TryFrom<&Chunk> for Text {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        expect_type(chunk, Text::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        Ok(Text {
+            keyword,
+            text: decode_latin1(rest),
+        })
+    }
+}
+
+impl CompressedText {
+    pub const CHUNK_TYPE: &'static str = "zTXt";
+
+    pub fn new(keyword: &str, text: &str) -> CompressedText {
+        CompressedText {
+            keyword: String::from(keyword),
+            text: String::from(text),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        let mut data = encode_keyword(&self.keyword)?;
+        data.push(0);
+        data.extend(deflate(&encode_latin1(&self.text)?)?);
+        Ok(Chunk::new(ChunkType::new(b"zTXt"), data))
+    }
+
+    /// Like `try_from`, but fails once the text inflates past `limits`
+    pub fn from_chunk_limited(chunk: &Chunk, limits: InflateLimits) -> Result<Self> {
+        expect_type(chunk, CompressedText::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+        match rest.split_first() {
+            Some((0, compressed)) => Ok(CompressedText {
+                keyword,
+                text: decode_latin1(&inflate_limited(compressed, limits)?),
+            }),
+            Some((method, _)) => Err(invalid(format!("unknown compression method {}", method))),
+            None => Err(invalid(String::from("missing compression method"))),
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for CompressedText {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        CompressedText::from_chunk_limited(chunk, InflateLimits::default())
+    }
+}
+
+impl InternationalText {
+    pub const CHUNK_TYPE: &'static str = "iTXt";
+
+    pub fn new(keyword: &str, text: &str) -> InternationalText {
+        InternationalText {
+            keyword: String::from(keyword),
+            compressed: false,
+            language_tag: String::new(),
+            translated_keyword: String::new(),
+            text: String::from(text),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        if self.language_tag.contains('\0') || self.translated_keyword.contains('\0') {
+            return Err(invalid(String::from(
+                "language tag and translated keyword can't contain null bytes",
+            )));
+        }
+
+        let mut data = encode_keyword(&self.keyword)?;
+        data.push(u8::from(self.compressed));
+        data.push(0);
+        data.extend_from_slice(self.language_tag.as_bytes());
+        data.push(0);
+        data.extend_from_slice(self.translated_keyword.as_bytes());
+        data.push(0);
+        if self.compressed {
+            data.extend(deflate(self.text.as_bytes())?);
+        } else {
+            data.extend_from_slice(self.text.as_bytes());
+        }
+        Ok(Chunk::new(ChunkType::new(b"iTXt"), data))
+    }
+
+    /// Like `try_from`, but fails once the text inflates past `limits`
+    pub fn from_chunk_limited(chunk: &Chunk, limits: InflateLimits) -> Result<Self> {
+        expect_type(chunk, InternationalText::CHUNK_TYPE)?;
+        let (keyword, rest) = split_keyword(chunk.data())?;
+
+        let (compressed, rest) = match rest {
+            [0, 0, rest @ ..] => (false, rest),
+            [1, 0, rest @ ..] => (true, rest),
+            [flag, method, ..] => {
+                return Err(invalid(format!(
+                    "unknown compression flag {} or method {}",
+                    flag, method
+                )))
+            }
+            _ => return Err(invalid(String::from("missing compression fields"))),
+        };
+
+        let (language_tag, rest) = split_at_null(rest)?;
+        let (translated_keyword, text) = split_at_null(rest)?;
+        let text = match compressed {
+            true => inflate_limited(text, limits)?,
+            false => text.to_vec(),
+        };
+
+        Ok(InternationalText {
+            keyword,
+            compressed,
+            language_tag: String::from_utf8(language_tag.to_vec())?,
+            translated_keyword: String::from_utf8(translated_keyword.to_vec())?,
+            text: String::from_utf8(text)?,
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for InternationalText {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        InternationalText::from_chunk_limited(chunk, InflateLimits::default())
+    }
+}
+
+impl TextEntry {
+    pub fn keyword(&self) -> &str {
+        match self {
+            TextEntry::Text(entry) => &entry.keyword,
+            TextEntry::Compressed(entry) => &entry.keyword,
+            TextEntry::International(entry) => &entry.keyword,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            TextEntry::Text(entry) => &entry.text,
+            TextEntry::Compressed(entry) => &entry.text,
+            TextEntry::International(entry) => &entry.text,
+        }
+    }
+
+    /// Whether `chunk` is one of the textual chunk types
+    pub fn is_text_chunk(chunk: &Chunk) -> bool {
+        [
+            Text::CHUNK_TYPE,
+            CompressedText::CHUNK_TYPE,
+            InternationalText::CHUNK_TYPE,
+        ]
+        .contains(&chunk.chunk_type().to_string().as_str())
+    }
+
+    /// Like `try_from`, but fails once compressed text inflates past `limits`
+    pub fn from_chunk_limited(chunk: &Chunk, limits: InflateLimits) -> Result<Self> {
+        match chunk.chunk_type().to_string().as_str() {
+            Text::CHUNK_TYPE => Text::try_from(chunk).map(TextEntry::Text),
+            CompressedText::CHUNK_TYPE => {
+                CompressedText::from_chunk_limited(chunk, limits).map(TextEntry::Compressed)
+            }
+            InternationalText::CHUNK_TYPE => {
+                InternationalText::from_chunk_limited(chunk, limits).map(TextEntry::International)
+            }
+            other => Err(invalid(format!("{} isn't a textual chunk", other))),
+        }
+    }
+
+    pub fn to_chunk(&self) -> Result<Chunk> {
+        match self {
+            TextEntry::Text(entry) => entry.to_chunk(),
+            TextEntry::Compressed(entry) => entry.to_chunk(),
+            TextEntry::International(entry) => entry.to_chunk(),
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for TextEntry {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self> {
+        TextEntry::from_chunk_limited(chunk, InflateLimits::default())
+    }
+}
+
+/// Whether `text` can be stored in a Latin-1 `tEXt` or `zTXt` chunk
+pub fn is_latin1(text: &str) -> bool {
+    text.chars().all(|c| u32::from(c) <= 0xff)
+}
+
+fn expect_type(chunk: &Chunk, chunk_type: &str) -> Result<()> {
+    match chunk.chunk_type().to_string() == chunk_type {
+        true => Ok(()),
+        _ => Err(invalid(format!(
+            "expected {}, found {}",
+            chunk_type,
+            chunk.chunk_type()
+        ))),
+    }
+}
+
+// @notice: Encodes a keyword followed by its null separator, checking the spec's rules
+fn encode_keyword(keyword: &str) -> Result<Vec<u8>> {
+    let mut bytes = encode_latin1(keyword)?;
+
+    let printable = bytes.iter().all(|&b| (32..=126).contains(&b) || b >= 161);
+    if bytes.is_empty()
+        || bytes.len() > MAXIMUM_KEYWORD_LENGTH
+        || !printable
+        || keyword.starts_with(' ')
+        || keyword.ends_with(' ')
+        || keyword.contains("  ")
+    {
+        return Err(invalid(format!("bad keyword {:?}", keyword)));
+    }
+
+    bytes.push(0);
+    Ok(bytes)
+}
+
+fn split_keyword(data: &[u8]) -> Result<(String, &[u8])> {
+    let (keyword, rest) = split_at_null(data)?;
+    if keyword.is_empty() || keyword.len() > MAXIMUM_KEYWORD_LENGTH {
+        return Err(invalid(format!("bad keyword length {}", keyword.len())));
+    }
+    Ok((decode_latin1(keyword), rest))
+}
+
+fn split_at_null(data: &[u8]) -> Result<(&[u8], &[u8])> {
+    let pos = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid(String::from("missing null separator")))?;
+    Ok((&data[..pos], &data[pos + 1..]))
+}
+
+fn encode_latin1(text: &str) -> Result<Vec<u8>> {
+    text.chars()
+        .map(|c| u8::try_from(c).map_err(|_| invalid(format!("{:?} isn't Latin-1", c))))
+        .collect()
+}
+
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidText(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_round_trip() {
+        let text = Text::new("Title", "Caf\u{e9} dice");
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(chunk.data()[..6], *b"Title\0");
+        assert_eq!(Text::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_compressed_text_round_trip() {
+        let text = CompressedText::new("Comment", &"a".repeat(1000));
+        let chunk = text.to_chunk().unwrap();
+        assert!(chunk.data().len() < 100);
+        assert_eq!(CompressedText::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_international_text_round_trip() {
+        let mut text = InternationalText::new("Title", "\u{30b5}\u{30a4}\u{30b3}\u{30ed}");
+        text.language_tag = String::from("ja");
+        text.translated_keyword = String::from("\u{984c}\u{540d}");
+        assert_eq!(
+            InternationalText::try_from(&text.to_chunk().unwrap()).unwrap(),
+            text
+        );
+
+        text.compressed = true;
+        assert_eq!(
+            InternationalText::try_from(&text.to_chunk().unwrap()).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn test_text_entry_from_chunk() {
+        let chunk = CompressedText::new("Author", "Me").to_chunk().unwrap();
+        let entry = TextEntry::try_from(&chunk).unwrap();
+        assert_eq!(entry.keyword(), "Author");
+        assert_eq!(entry.text(), "Me");
+        assert!(TextEntry::is_text_chunk(&chunk));
+    }
+
+    #[test]
+    fn test_compressed_text_bomb() {
+        let text = "0".repeat(1 << 20);
+        let chunk = CompressedText::new("Bomb", &text).to_chunk().unwrap();
+        assert_eq!(CompressedText::try_from(&chunk).unwrap().text, text);
+
+        let limits = InflateLimits {
+            max_ratio: 100,
+            ..InflateLimits::default()
+        };
+        assert!(matches!(
+            TextEntry::from_chunk_limited(&chunk, limits),
+            Err(Error::LimitExceeded {
+                limit: "decompression ratio",
+                ..
+            })
+        ));
+
+        let mut text = InternationalText::new("Bomb", &text);
+        text.compressed = true;
+        let limits = InflateLimits {
+            max_size: 1 << 16,
+            ..InflateLimits::default()
+        };
+        assert!(matches!(
+            TextEntry::from_chunk_limited(&text.to_chunk().unwrap(), limits),
+            Err(Error::LimitExceeded {
+                limit: "inflated size",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_keywords() {
+        assert!(Text::new("", "value").to_chunk().is_err());
+        assert!(Text::new(" Title", "value").to_chunk().is_err());
+        assert!(Text::new("Two  spaces", "value").to_chunk().is_err());
+        assert!(Text::new(&"k".repeat(80), "value").to_chunk().is_err());
+        assert!(Text::new("Title", "\u{263a}").to_chunk().is_err());
+    }
+
+    #[test]
+    fn test_invalid_text_chunks() {
+        let chunk = Chunk::new(ChunkType::new(b"tEXt"), b"no separator".to_vec());
+        assert!(matches!(Text::try_from(&chunk), Err(Error::InvalidText(_))));
+
+        let chunk = Chunk::new(ChunkType::new(b"zTXt"), b"Title\0\0garbage".to_vec());
+        assert!(matches!(
+            CompressedText::try_from(&chunk),
+            Err(Error::Decompression(_))
+        ));
+    }
+}
@@ -0,0 +1,405 @@
+// Checks the chunk ordering and multiplicity rules of the PNG spec, which parsing
+// alone doesn't enforce
+use std::fmt::{self, Display};
+
+use serde::Serialize;
+
+use crate::ihdr::{ColorType, Ihdr};
+use crate::png::Png;
+
+/// Critical chunks this crate understands, decoders must reject any other
+const KNOWN_CRITICAL: [&str; 4] = ["IHDR", "PLTE", "IDAT", "IEND"];
+/// Ancillary chunks the spec allows at most once
+const SINGLE_ANCILLARY: [&str; 14] = [
+    "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "cICP", "mDCV", "cLLI", "bKGD", "hIST", "tRNS", "eXIf",
+    "pHYs", "tIME",
+];
+/// Chunks that must come before PLTE and IDAT
+const BEFORE_PLTE: [&str; 5] = ["cHRM", "gAMA", "iCCP", "sBIT", "sRGB"];
+/// Chunks that must come after PLTE, when there is one, but before IDAT
+const AFTER_PLTE: [&str; 3] = ["bKGD", "hIST", "tRNS"];
+/// Chunks that must come before IDAT, wherever PLTE is
+const BEFORE_IDAT: [&str; 3] = ["pHYs", "sPLT", "eXIf"];
+
+/// How bad a diagnostic is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The file breaks a rule decoders rely on
+    Error,
+    /// The file is readable but breaks a rule decoders are expected to tolerate
+    Warning,
+}
+
+/// A broken rule
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Problem {
+    /// A required chunk is absent
+    MissingChunk { chunk_type: String },
+    /// IHDR isn't the first chunk
+    MisplacedIhdr,
+    /// IHDR doesn't decode
+    InvalidIhdr { reason: String },
+    /// A chunk allowed once appears again
+    DuplicateChunk { chunk_type: String },
+    /// A palette in a greyscale image
+    UnexpectedPalette,
+    /// Other chunks come between IDAT chunks
+    SplitImageData,
+    /// `chunk_type` comes after `other`, which it must precede
+    ChunkTooLate { chunk_type: String, other: String },
+    /// `chunk_type` comes before `other`, which it must follow
+    ChunkTooEarly { chunk_type: String, other: String },
+    /// Anything after IEND
+    ChunkAfterIend { chunk_type: String },
+    /// A critical chunk not in the spec
+    UnknownCriticalChunk { chunk_type: String },
+    /// Both iCCP and sRGB describe the colour space
+    ConflictingColorSpace,
+    /// Bytes that aren't chunks follow IEND
+    TrailingData { length: usize },
+}
+
+/// One rule `png` breaks, and where
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Diagnostic {
+    /// Position of the offending chunk, `None` for problems with the file as a whole
+    pub index: Option<usize>,
+    pub severity: Severity,
+    #[serde(flatten)]
+    pub problem: Problem,
+}
+
+impl Problem {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Problem::DuplicateChunk { chunk_type } if !is_critical(chunk_type) => Severity::Warning,
+            Problem::ConflictingColorSpace | Problem::TrailingData { .. } => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::MissingChunk { chunk_type } => write!(f, "no {} chunk", chunk_type),
+            Problem::MisplacedIhdr => write!(f, "IHDR must be the first chunk"),
+            Problem::InvalidIhdr { reason } => write!(f, "invalid IHDR: {}", reason),
+            Problem::DuplicateChunk { chunk_type } => {
+                write!(f, "{} can only appear once", chunk_type)
+            }
+            Problem::UnexpectedPalette => write!(f, "greyscale images can't have a PLTE chunk"),
+            Problem::SplitImageData => write!(f, "IDAT chunks must be consecutive"),
+            Problem::ChunkTooLate { chunk_type, other } => {
+                write!(f, "{} must come before {}", chunk_type, other)
+            }
+            Problem::ChunkTooEarly { chunk_type, other } => {
+                write!(f, "{} must come after {}", chunk_type, other)
+            }
+            Problem::ChunkAfterIend { chunk_type } => {
+                write!(f, "{} comes after IEND", chunk_type)
+            }
+            Problem::UnknownCriticalChunk { chunk_type } => {
+                write!(f, "unknown critical chunk {}", chunk_type)
+            }
+            Problem::ConflictingColorSpace => {
+                write!(f, "iCCP and sRGB shouldn't both be present")
+            }
+            Problem::TrailingData { length } => {
+                write!(f, "{} bytes of data follow IEND", length)
+            }
+        }
+    }
+}
+
+impl Diagnostic {
+    pub fn new(index: Option<usize>, problem: Problem) -> Diagnostic {
+        Diagnostic {
+            index,
+            severity: problem.severity(),
+            problem,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let index = self.index.map_or(String::from("-"), |i| i.to_string());
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{:>4}  {:<7}  {}", index, severity, self.problem)
+    }
+}
+
+/// Every ordering and multiplicity rule `png` breaks, in file order of the chunks involved
+pub fn validate(png: &Png) -> Vec<Diagnostic> {
+    let types: Vec<String> = png
+        .chunks()
+        .iter()
+        .map(|c| c.chunk_type().to_string())
+        .collect();
+    let first = |chunk_type: &str| types.iter().position(|t| t == chunk_type);
+    let mut diagnostics = Vec::new();
+
+    let ihdr = match first("IHDR") {
+        None => {
+            diagnostics.push(missing(Ihdr::CHUNK_TYPE));
+            None
+        }
+        Some(index) => {
+            if index != 0 {
+                diagnostics.push(Diagnostic::new(Some(index), Problem::MisplacedIhdr));
+            }
+            match Ihdr::try_from(&png.chunks()[index]) {
+                Ok(ihdr) => Some(ihdr),
+                Err(why) => {
+                    let reason = why.to_string();
+                    diagnostics.push(Diagnostic::new(
+                        Some(index),
+                        Problem::InvalidIhdr { reason },
+                    ));
+                    None
+                }
+            }
+        }
+    };
+    let plte = first("PLTE");
+    let idat = first("IDAT");
+    let iend = first("IEND");
+
+    let mut previous_idat = None;
+    for (index, chunk_type) in types.iter().enumerate() {
+        let problem = |problem| Diagnostic::new(Some(index), problem);
+        let before = |other: Option<usize>| other.is_some_and(|other| other < index);
+
+        if types[..index].contains(chunk_type)
+            && (KNOWN_CRITICAL.contains(&chunk_type.as_str()) && chunk_type != "IDAT"
+                || SINGLE_ANCILLARY.contains(&chunk_type.as_str()))
+        {
+            diagnostics.push(problem(Problem::DuplicateChunk {
+                chunk_type: chunk_type.clone(),
+            }));
+            continue;
+        }
+        if before(iend) {
+            diagnostics.push(problem(Problem::ChunkAfterIend {
+                chunk_type: chunk_type.clone(),
+            }));
+            continue;
+        }
+
+        let name = chunk_type.as_str();
+        if is_critical(name) && !KNOWN_CRITICAL.contains(&name) {
+            diagnostics.push(problem(Problem::UnknownCriticalChunk {
+                chunk_type: chunk_type.clone(),
+            }));
+        }
+        if name == "IDAT" {
+            if previous_idat.is_some_and(|previous| previous + 1 != index) {
+                diagnostics.push(problem(Problem::SplitImageData));
+            }
+            previous_idat = Some(index);
+        }
+
+        // The chunk that had to come later but is already behind this one
+        let passed = if BEFORE_PLTE.contains(&name) {
+            [("PLTE", plte), ("IDAT", idat)]
+                .into_iter()
+                .find(|(_, i)| before(*i))
+        } else if name == "PLTE" || AFTER_PLTE.contains(&name) || BEFORE_IDAT.contains(&name) {
+            Some(("IDAT", idat)).filter(|(_, i)| before(*i))
+        } else {
+            None
+        };
+        if let Some((other, _)) = passed {
+            diagnostics.push(problem(Problem::ChunkTooLate {
+                chunk_type: chunk_type.clone(),
+                other: String::from(other),
+            }));
+        } else if AFTER_PLTE.contains(&name) && plte.is_some_and(|plte| plte > index) {
+            diagnostics.push(problem(Problem::ChunkTooEarly {
+                chunk_type: chunk_type.clone(),
+                other: String::from("PLTE"),
+            }));
+        }
+    }
+
+    if let Some(ihdr) = ihdr {
+        match (ihdr.color_type, plte) {
+            (ColorType::Indexed, None) => diagnostics.push(missing("PLTE")),
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(index)) => {
+                diagnostics.push(Diagnostic::new(Some(index), Problem::UnexpectedPalette))
+            }
+            _ => {}
+        }
+    }
+    if idat.is_none() {
+        diagnostics.push(missing("IDAT"));
+    }
+    if iend.is_none() {
+        diagnostics.push(missing("IEND"));
+    }
+    if let (Some(_), Some(srgb)) = (first("iCCP"), first("sRGB")) {
+        diagnostics.push(Diagnostic::new(Some(srgb), Problem::ConflictingColorSpace));
+    }
+    if !png.trailing_data().is_empty() {
+        diagnostics.push(Diagnostic::new(
+            None,
+            Problem::TrailingData {
+                length: png.trailing_data().len(),
+            },
+        ));
+    }
+    diagnostics.sort_by_key(|d| d.index.unwrap_or(usize::MAX));
+    diagnostics
+}
+
+// @notice: Whether a chunk type's first letter is uppercase, so it's critical
+fn is_critical(chunk_type: &str) -> bool {
+    chunk_type.starts_with(|c: char| c.is_ascii_uppercase())
+}
+
+fn missing(chunk_type: &str) -> Diagnostic {
+    Diagnostic::new(
+        None,
+        Problem::MissingChunk {
+            chunk_type: String::from(chunk_type),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::ihdr::InterlaceMethod;
+    use std::str::FromStr;
+
+    fn testing_png(types: &[&str], color_type: ColorType) -> Png {
+        let ihdr = Ihdr::new(1, 1, 8, color_type, InterlaceMethod::None).unwrap();
+        let chunks = types
+            .iter()
+            .map(|&chunk_type| match chunk_type {
+                "IHDR" => ihdr.to_chunk(),
+                _ => Chunk::new(ChunkType::from_str(chunk_type).unwrap(), Vec::new()),
+            })
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    fn problems(png: &Png) -> Vec<(Option<usize>, Problem)> {
+        validate(png)
+            .into_iter()
+            .map(|d| (d.index, d.problem))
+            .collect()
+    }
+
+    #[test]
+    fn test_valid_png() {
+        let png = testing_png(
+            &[
+                "IHDR", "gAMA", "PLTE", "tRNS", "IDAT", "IDAT", "tEXt", "IEND",
+            ],
+            ColorType::Indexed,
+        );
+        assert_eq!(validate(&png), []);
+    }
+
+    #[test]
+    fn test_structural_errors() {
+        let png = testing_png(
+            &["ruSt", "IHDR", "IHDR", "IDAT", "tEXt", "IDAT"],
+            ColorType::Rgb,
+        );
+        assert_eq!(
+            problems(&png),
+            [
+                (Some(1), Problem::MisplacedIhdr),
+                (
+                    Some(2),
+                    Problem::DuplicateChunk {
+                        chunk_type: String::from("IHDR")
+                    }
+                ),
+                (Some(5), Problem::SplitImageData),
+                (
+                    None,
+                    Problem::MissingChunk {
+                        chunk_type: String::from("IEND")
+                    }
+                ),
+            ]
+        );
+        assert_eq!(validate(&testing_png(&[], ColorType::Rgb)).len(), 3);
+    }
+
+    #[test]
+    fn test_ordering_errors() {
+        let png = testing_png(
+            &[
+                "IHDR", "bKGD", "PLTE", "IDAT", "gAMA", "PLTE", "IEND", "tEXt",
+            ],
+            ColorType::Rgb,
+        );
+        let problems = problems(&png);
+        let too_late = |chunk_type: &str, other: &str| Problem::ChunkTooLate {
+            chunk_type: String::from(chunk_type),
+            other: String::from(other),
+        };
+        assert_eq!(
+            problems,
+            [
+                (
+                    Some(1),
+                    Problem::ChunkTooEarly {
+                        chunk_type: String::from("bKGD"),
+                        other: String::from("PLTE")
+                    }
+                ),
+                (Some(4), too_late("gAMA", "PLTE")),
+                (
+                    Some(5),
+                    Problem::DuplicateChunk {
+                        chunk_type: String::from("PLTE")
+                    }
+                ),
+                (
+                    Some(7),
+                    Problem::ChunkAfterIend {
+                        chunk_type: String::from("tEXt")
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_palette_rules_and_warnings() {
+        let png = testing_png(&["IHDR", "IDAT", "IEND"], ColorType::Indexed);
+        assert!(validate(&png)[0].is_error());
+
+        let png = testing_png(
+            &["IHDR", "sRGB", "iCCP", "PLTE", "IDAT", "IEND", "PLTE"],
+            ColorType::GrayscaleAlpha,
+        );
+        let diagnostics = validate(&png);
+        assert_eq!(diagnostics[0].problem, Problem::ConflictingColorSpace);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[1].problem, Problem::UnexpectedPalette);
+        assert!(diagnostics[2].is_error());
+
+        let png = testing_png(&["IHDR", "tIME", "tIME", "IDAT", "IEND"], ColorType::Rgb);
+        assert_eq!(validate(&png)[0].severity, Severity::Warning);
+        let png = testing_png(&["IHDR", "IDAT", "ABCD", "IEND"], ColorType::Rgb);
+        assert!(validate(&png)[0].is_error());
+    }
+}
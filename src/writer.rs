@@ -0,0 +1,140 @@
+use std::io::{Read, Write};
+
+use crate::chunk::Chunk;
+use crate::error::Result;
+use crate::png::Png;
+use crate::reader::ChunkReader;
+
+/// Writes chunks one at a time to any `Write`, the counterpart of `ChunkReader`
+pub struct ChunkWriter<W: Write> {
+    writer: W,
+    offset: usize,
+}
+
+impl<W: Write> ChunkWriter<W> {
+    /// Writes the PNG signature, leaving the writer ready for the first chunk
+    pub fn new(mut writer: W) -> Result<ChunkWriter<W>> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+
+        Ok(ChunkWriter {
+            writer,
+            offset: Png::STANDARD_HEADER.len(),
+        })
+    }
+
+    /// Number of bytes written so far, including the signature
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        chunk.write_to(&mut self.writer)?;
+        self.offset += 12 + chunk.data().len();
+        Ok(())
+    }
+
+    /// Writes bytes that aren't a chunk, such as data kept after IEND
+    pub fn write_raw(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        self.offset += data.len();
+        Ok(())
+    }
+
+    /// Copies every chunk of `reader` through `edit`, writing whatever chunks it returns.
+    /// Returning `vec![chunk]` passes a chunk through, an empty `Vec` drops it and extra
+    /// chunks are written in the order given. Anything after IEND is copied as it is.
+    pub fn copy_from<R, F>(&mut self, mut reader: ChunkReader<R>, mut edit: F) -> Result<()>
+    where
+        R: Read,
+        F: FnMut(Chunk) -> Vec<Chunk>,
+    {
+        for chunk in &mut reader {
+            for chunk in edit(chunk?) {
+                self.write_chunk(&chunk)?;
+            }
+        }
+        if reader.ended() {
+            self.write_raw(&reader.read_remaining(u64::MAX)?)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes and hands back the underlying writer
+    pub fn into_inner(mut self) -> Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.as_bytes().to_vec(),
+        )
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("IDAT", "pixels"),
+            chunk("ruSt", "Old message"),
+            chunk("IEND", ""),
+        ])
+    }
+
+    #[test]
+    fn test_write_chunks() {
+        let png = testing_png();
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        for chunk in png.chunks() {
+            writer.write_chunk(chunk).unwrap();
+        }
+        assert_eq!(writer.offset(), png.as_bytes().len());
+        assert_eq!(writer.into_inner().unwrap(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_copy_from_inject_and_drop() {
+        let bytes = testing_png().as_bytes();
+        let reader = ChunkReader::new(bytes.as_slice()).unwrap();
+
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        writer
+            .copy_from(reader, |c| match c.chunk_type().to_string().as_str() {
+                "ruSt" => Vec::new(),
+                "IEND" => vec![chunk("ruSt", "New message"), c],
+                _ => vec![c],
+            })
+            .unwrap();
+
+        let png = Png::try_from(writer.into_inner().unwrap().as_slice()).unwrap();
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(
+            png.chunk_by_type("ruSt").unwrap().data_as_string().unwrap(),
+            "New message"
+        );
+    }
+
+    #[test]
+    fn test_copy_from_keeps_trailing_data() {
+        let mut bytes = testing_png().as_bytes();
+        bytes.extend_from_slice(b"PK\x03\x04 appended zip");
+        let reader = ChunkReader::new(bytes.as_slice()).unwrap();
+
+        let mut writer = ChunkWriter::new(Vec::new()).unwrap();
+        writer.copy_from(reader, |c| vec![c]).unwrap();
+        assert_eq!(writer.offset(), bytes.len());
+        assert_eq!(writer.into_inner().unwrap(), bytes);
+    }
+}
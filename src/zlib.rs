@@ -0,0 +1,112 @@
+use std::io::{Read, Write};
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use crate::error::{Error, Result};
+
+/// Most a deflate stream can expand by, so limiting inflation to it never rejects valid data
+pub(crate) const MAX_DEFLATE_RATIO: u32 = 1032;
+
+/// Most bytes inflating produces unless told otherwise, since even a stream within the
+/// ratio can expand a large chunk into gigabytes
+pub(crate) const MAX_INFLATED_SIZE: u64 = 1 << 30;
+
+/// How far inflating untrusted data may go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InflateLimits {
+    /// Most the output may expand by, as a multiple of the input
+    pub max_ratio: u32,
+    /// Most bytes the output may hold
+    pub max_size: u64,
+}
+
+impl Default for InflateLimits {
+    fn default() -> Self {
+        InflateLimits {
+            max_ratio: MAX_DEFLATE_RATIO,
+            max_size: MAX_INFLATED_SIZE,
+        }
+    }
+}
+
+pub(crate) fn deflate(bytes: &[u8]) -> Result<Vec<u8>> {
+    deflate_with(bytes, Compression::default())
+}
+
+pub(crate) fn deflate_with(bytes: &[u8], level: Compression) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), level);
+    encoder.write_all(bytes)?;
+    Ok(encoder.finish()?)
+}
+
+/// Inflates `bytes`, failing once the output grows past `limits` rather than letting a
+/// zlib bomb fill memory
+pub(crate) fn inflate_limited(bytes: &[u8], limits: InflateLimits) -> Result<Vec<u8>> {
+    let by_ratio = (bytes.len() as u64).saturating_mul(u64::from(limits.max_ratio));
+    let maximum = by_ratio.min(limits.max_size);
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(maximum.saturating_add(1))
+        .read_to_end(&mut inflated)
+        .map_err(|why| Error::Decompression(why.to_string()))?;
+    if inflated.len() as u64 > maximum {
+        return Err(match by_ratio > limits.max_size {
+            true => Error::LimitExceeded {
+                limit: "inflated size",
+                maximum: limits.max_size,
+            },
+            false => Error::LimitExceeded {
+                limit: "decompression ratio",
+                maximum: u64::from(limits.max_ratio),
+            },
+        });
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inflate_limited() {
+        let bomb = deflate(&vec![0; 1 << 20]).unwrap();
+        assert_eq!(
+            inflate_limited(&bomb, InflateLimits::default())
+                .unwrap()
+                .len(),
+            1 << 20
+        );
+        let limits = InflateLimits {
+            max_ratio: 100,
+            ..InflateLimits::default()
+        };
+        assert!(matches!(
+            inflate_limited(&bomb, limits),
+            Err(Error::LimitExceeded { maximum: 100, .. })
+        ));
+        let limits = InflateLimits {
+            max_size: 1000,
+            ..InflateLimits::default()
+        };
+        assert!(matches!(
+            inflate_limited(&bomb, limits),
+            Err(Error::LimitExceeded {
+                limit: "inflated size",
+                maximum: 1000
+            })
+        ));
+
+        let text = deflate(b"not very compressible").unwrap();
+        let limits = InflateLimits {
+            max_ratio: 2,
+            max_size: 21,
+        };
+        assert_eq!(
+            inflate_limited(&text, limits).unwrap(),
+            b"not very compressible"
+        );
+    }
+}